@@ -0,0 +1,175 @@
+//! Types and names given by hand for variables the VM code says too little
+//! about. The file is a small subset of TOML: `key = "value"` lines, `#`
+//! comments and `[prefix]` headers that are put in front of the keys below
+//! them. Keys name a variable or a subroutine's result, and `.name` after a
+//! variable names it in Jack:
+//!
+//! ```toml
+//! Main.main.local.2 = "Array"
+//! Main.main.local.2.name = "scores"
+//!
+//! [Ball]
+//! field.0 = "int"
+//! static.1 = "Ball"
+//! move.argument.1.name = "dx"
+//! bounce.return = "Ball"
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use diagnostic::Diagnostic;
+use parser::Span;
+use types::Type;
+
+/// A variable, or a subroutine result, an annotation is about.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// `Class.sub.local.i`, by the subroutine's full name.
+    Local(String, usize),
+    /// `Class.sub.argument.i`, counting a method's object as argument 0.
+    Argument(String, usize),
+    Field(String, usize),
+    Static(String, usize),
+    /// `Class.sub.return`, what the subroutine returns.
+    Result(String),
+}
+
+impl Target {
+    /// The variable's name in the untyped IR, or `None` for a result.
+    pub fn ir_name(&self) -> Option<String> {
+        Some(match self {
+            Target::Local(_, i) => format!("LCL_{}", i),
+            Target::Argument(_, i) => format!("ARG_{}", i),
+            Target::Field(_, i) => format!("field_{}", i),
+            Target::Static(class, i) => format!("{}.STATIC_{}", class, i),
+            Target::Result(_) => return None,
+        })
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Local(sub, i) => write!(f, "{}.local.{}", sub, i),
+            Target::Argument(sub, i) => write!(f, "{}.argument.{}", sub, i),
+            Target::Field(class, i) => write!(f, "{}.field.{}", class, i),
+            Target::Static(class, i) => write!(f, "{}.static.{}", class, i),
+            Target::Result(sub) => write!(f, "{}.return", sub),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Annotations {
+    pub types: HashMap<Target, (Type, Span)>,
+    pub names: HashMap<Target, (String, Span)>,
+}
+
+const KEYWORDS: [&str; 21] = [
+    "class", "constructor", "function", "method", "field", "static", "var",
+    "int", "char", "boolean", "void", "true", "false", "null", "this",
+    "let", "do", "if", "else", "while", "return",
+];
+
+fn is_identifier(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+        s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+        !KEYWORDS.contains(&s)
+}
+
+/// Whether `s` spells a Jack type. Whether a class name names a class of
+/// the program is only known once the program is read; see
+/// `types::infer`.
+fn is_type(s: &str) -> bool {
+    s == "int" || s == "char" || s == "boolean" || is_identifier(s)
+}
+
+/// Reads a key, split at its dots, as a target and whether it is about
+/// the target's name rather than its type.
+fn target(parts: &[&str]) -> Option<(Target, bool)> {
+    let (parts, name) = match parts.split_last() {
+        Some((&"name", rest)) => (rest, true),
+        _ => (parts, false),
+    };
+    if let [class, sub, "return"] = parts {
+        return Some((Target::Result(format!("{}.{}", class, sub)), name));
+    }
+    let index = parts.last()?.parse().ok()?;
+    let target = match parts {
+        [class, "field", _] => Target::Field(class.to_string(), index),
+        [class, "static", _] => Target::Static(class.to_string(), index),
+        [class, sub, "local", _] => Target::Local(format!("{}.{}", class, sub), index),
+        [class, sub, "argument", _] => Target::Argument(format!("{}.{}", class, sub), index),
+        _ => return None,
+    };
+    Some((target, name))
+}
+
+impl Annotations {
+    /// Parses the annotation file `file`, reporting every malformed line.
+    pub fn parse(file: &str, source: &str) -> Result<Annotations, Vec<Diagnostic>> {
+        let file: Rc<str> = file.into();
+        let mut annotations = Annotations::default();
+        let mut errors = Vec::new();
+        let mut prefix = String::new();
+        for (i, line) in source.lines().enumerate() {
+            let span = Span::new(file.clone(), i + 1);
+            let line = match line.find('#') {
+                // A `#` inside the quoted value is not a comment, but no
+                // valid value holds one.
+                Some(hash) => &line[..hash],
+                None => line,
+            }.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                prefix = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+            let mut error = |message: String| errors.push(Diagnostic::error(Some(span.clone()), message));
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    error(format!("expected `key = \"value\"`, found `{}`", line));
+                    continue;
+                }
+            };
+            let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(v) => v,
+                None => {
+                    error(format!("the value of `{}` must be a quoted string", key));
+                    continue;
+                }
+            };
+            let key = if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+            let parts: Vec<&str> = key.split('.').map(str::trim).collect();
+            let (target, is_name) = match target(&parts) {
+                Some(t) => t,
+                None => {
+                    error(format!("`{}` does not name a local, argument, field, static or result", key));
+                    continue;
+                }
+            };
+            if is_name && target.ir_name().is_none() {
+                error(format!("`{}` is not a variable and cannot be named", target));
+            } else if is_name && !is_identifier(value) {
+                error(format!("`{}` is not a Jack identifier", value));
+            } else if !is_name && !is_type(value) {
+                error(format!("`{}` is not a Jack type", value));
+            } else if is_name {
+                annotations.names.insert(target, (value.to_string(), span));
+            } else {
+                annotations.types.insert(target, (Type::from_name(value), span));
+            }
+        }
+        if errors.is_empty() {
+            Ok(annotations)
+        } else {
+            Err(errors)
+        }
+    }
+}
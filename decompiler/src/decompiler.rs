@@ -1,49 +1,166 @@
-use parser::{VmCommand};
+use parser::{VmCommand, Span, Spanned};
+use std::error::Error;
+use std::fmt;
 use std::fmt::Display;
+use std::mem;
 use untyped_ir::*;
+use program::{Function, Program};
+use dominators::DominatorTree;
+use loops::{Loop, LoopForest};
+use diagnostic::Diagnostic;
+use params;
+use stack;
+use stack::StackHeights;
 
+use std::io;
 use std::io::Write;
-use std::iter;
 use std::collections::HashMap;
 
+/// Why control may pass along an edge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    /// Into the block that follows in the source.
+    FallThrough,
+    Goto,
+    /// The jump of an `if-goto`, taken when the popped value is true.
+    CondTaken,
+    /// Past an `if-goto` whose popped value is false.
+    CondNotTaken,
+    /// From a block ending in `return` to the exit node.
+    Return,
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            EdgeKind::FallThrough => "fall-through",
+            EdgeKind::Goto => "goto",
+            EdgeKind::CondTaken => "if-goto",
+            EdgeKind::CondNotTaken => "not-taken",
+            EdgeKind::Return => "return",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
 struct BasicBlock<CmdType> {
     index: usize,
-    neighbors: Vec<usize>,
+    edges: Vec<Edge>,
     label: Option<String>,
     commands: Vec<CmdType>,
-}
-
-impl<CmdType> BasicBlock<CmdType> {
-    fn is_empty(&self) -> bool {
-        self.neighbors.is_empty() && self.label.is_none() && self.commands.is_empty()
-    }
+    /// The `goto`, `if-goto` or `return` that ends the block, if any.
+    end: Option<Span>,
 }
 
 impl<CmdType> Default for BasicBlock<CmdType> {
     fn default() -> Self {
         BasicBlock {
             index: 0,
-            neighbors: Vec::new(),
+            edges: Vec::new(),
             label: None,
             commands: Vec::new(),
+            end: None,
         }
     }
 }
 
 pub struct Graph<CmdType> {
     nodes: Vec<BasicBlock<CmdType>>,
-    edge_label: HashMap<(usize, usize), &'static str>,
+    /// The nodes with an edge to each node, kept in step with the edges.
+    preds: Vec<Vec<usize>>,
+    /// The empty node every `return` leads to.
+    exit: usize,
+}
+
+/// The control-dependence graph of a `Graph`, as computed by
+/// `Graph::control_dependence`.
+pub struct ControlDependence {
+    deps: Vec<Vec<(usize, EdgeKind)>>,
+}
+
+impl ControlDependence {
+    /// The branch nodes `node` is control dependent on, each with the kind
+    /// of edge that commits to running `node`.
+    pub fn dependences(&self, node: usize) -> &[(usize, EdgeKind)] {
+        &self.deps[node]
+    }
+
+    /// The nodes that are control dependent on the branch `node`.
+    pub fn dependents<'a>(&'a self, node: usize) -> impl Iterator<Item = usize> + 'a {
+        self.deps
+            .iter()
+            .enumerate()
+            .filter(move |(_, d)| d.iter().any(|&(x, _)| x == node))
+            .map(|(y, _)| y)
+    }
+}
+
+/// A function body whose jumps do not form a well-formed graph.
+#[derive(Debug)]
+pub enum CfgError {
+    UndefinedLabel(String, Span),
+    DuplicateLabel(String, Span),
+    /// A block that should end in a two-way branch but lacks its taken or
+    /// not-taken edge, or the condition deciding between them.
+    MalformedBranch(usize, Option<String>),
+    /// A loop, identified by its header block, that leaves to more than one
+    /// place.
+    UnstructuredLoop(usize, Option<String>),
+    /// Jumps into the middle of a loop, naming the blocks at both ends of
+    /// the offending edges by label where there is one.
+    Irreducible(Vec<String>),
+    /// A function whose operand stack `stack::verify` found problems with.
+    UnbalancedStack(String),
 }
 
-impl From<Graph<VmCommand>> for Graph<UnTypedIR> {
-    fn from(vm: Graph<VmCommand>) -> Self {
-        let mut graph: Graph<UnTypedIR> = Graph { nodes: Vec::new(), edge_label: vm.edge_label.clone()};
+impl fmt::Display for CfgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CfgError::UndefinedLabel(label, span) => write!(f, "{}: jump to undefined label `{}`", span, label),
+            CfgError::DuplicateLabel(label, span) => write!(f, "{}: label `{}` defined twice", span, label),
+            CfgError::MalformedBranch(block, None) => write!(f, "block {} is not a well-formed branch", block),
+            CfgError::MalformedBranch(block, Some(label)) => {
+                write!(f, "block {} (label `{}`) is not a well-formed branch", block, label)
+            }
+            CfgError::UnstructuredLoop(block, None) => write!(f, "loop at block {} has more than one exit", block),
+            CfgError::UnstructuredLoop(block, Some(label)) => {
+                write!(f, "loop at block {} (label `{}`) has more than one exit", block, label)
+            }
+            CfgError::UnbalancedStack(function) => write!(f, "{}: operand stack is not balanced", function),
+            CfgError::Irreducible(labels) => {
+                let labels: Vec<String> = labels.iter().map(|l| format!("`{}`", l)).collect();
+                write!(f, "jumps enter a loop other than at its start, between {}", labels.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for CfgError {}
+
+impl Graph<UnTypedIR> {
+    /// Lifts every block of `vm` to untyped IR. `heights` are the stack
+    /// depths `stack::verify` found, which say how many values each block
+    /// passes on to the next. `class` names the class file the function came
+    /// from.
+    pub fn from_vm(vm: Graph<Spanned<VmCommand>>, heights: &StackHeights, class: &str) -> Self {
+        let mut graph: Graph<UnTypedIR> = Graph { nodes: Vec::new(), preds: vm.preds.clone(), exit: vm.exit };
         for i in 0..vm.nodes.len() {
             graph.nodes.push(BasicBlock {
                 index: vm.nodes[i].index,
-                neighbors: vm.nodes[i].neighbors.clone(),
+                edges: vm.nodes[i].edges.clone(),
                 label: vm.nodes[i].label.clone(),
-                commands: get_untyped_ir_from_vm_commands(&vm.nodes[i].commands),
+                commands: get_untyped_ir_from_vm_commands(
+                    &vm.nodes[i].commands,
+                    class,
+                    heights.at_entry(i).unwrap_or(0),
+                    heights.at_exit(i).unwrap_or(0),
+                ),
+                end: vm.nodes[i].end.clone(),
             })
         }
         graph
@@ -51,380 +168,727 @@ impl From<Graph<VmCommand>> for Graph<UnTypedIR> {
 }
 
 impl<CmdType: Display> Graph<CmdType> {
-    fn find_node_by_label(&self, label: &str) -> Option<usize> {
-        self.nodes
-            .iter()
-            .position(|n| n.label.is_some() && n.label.as_ref().unwrap() == label)
-    }
-
-    fn pred(&self, node: usize) -> Vec<usize> {
-        self.nodes
-            .iter()
-            .enumerate()
-            .filter_map(|(index, n)| if n.neighbors.iter().any(|&i| i == node) {
-                Some(index)
-            } else {
-                None
-            })
-            .collect()
+    /// The nodes that have an edge to `node`.
+    pub fn pred(&self, node: usize) -> &[usize] {
+        &self.preds[node]
     }
 
     fn add_node(&mut self, mut node: BasicBlock<CmdType>) -> usize {
         let index = self.nodes.len();
         node.index = index;
         self.nodes.push(node);
-        return index;
+        self.preds.push(Vec::new());
+        index
     }
 
-    fn add_edge(&mut self, s: usize, d: usize) {
-        if self.nodes[s].neighbors.iter().find(|&n| *n == d).is_none() {
-            self.nodes[s].neighbors.push(d)
+    fn add_edge(&mut self, s: usize, d: usize, kind: EdgeKind) {
+        self.nodes[s].edges.push(Edge { to: d, kind });
+        if !self.preds[d].contains(&s) {
+            self.preds[d].push(s);
         }
     }
 
-    fn shrink(&mut self) {
-        let mut to_remove = Vec::new();
-        for i in 0..self.nodes.len() {
-            let incoming = !self.pred(i).is_empty();
-            if self.nodes[i].label.is_none() && self.nodes[i].commands.is_empty() && !incoming {
-                to_remove.push(i);
-            }
-        }
-        for t in to_remove {
-            self.nodes[t].neighbors.clear();
-        }
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
     }
 
-    pub fn loop_headers(&self, dom: &Vec<Vec<usize>>) -> Vec<usize> {
-        let mut result: Vec<usize> = iter::repeat(0).take(self.nodes.len()).collect();
-        for i in 0..self.nodes.len() {
-            for n in &self.nodes[i].neighbors {
-                if dom[i][*n] == 1 {
-                    result[*n] = 1;
-                }
+    /// The empty node every `return` leads to.
+    pub fn exit(&self) -> usize {
+        self.exit
+    }
+
+    pub fn commands(&self, node: usize) -> &[CmdType] {
+        &self.nodes[node].commands
+    }
+
+    pub fn label(&self, node: usize) -> Option<&str> {
+        self.nodes[node].label.as_deref()
+    }
+
+    /// Where the jump or `return` ending `node` is in the source.
+    pub fn end(&self, node: usize) -> Option<&Span> {
+        self.nodes[node].end.as_ref()
+    }
+
+    pub fn edges(&self, node: usize) -> &[Edge] {
+        &self.nodes[node].edges
+    }
+
+    /// The nodes `node` has an edge to.
+    pub fn successors<'a>(&'a self, node: usize) -> impl Iterator<Item = usize> + 'a {
+        self.nodes[node].edges.iter().map(|e| e.to)
+    }
+
+    /// The taken and not-taken edges of a node ending in `if-goto`, or
+    /// `None` if the node is not a two-way branch.
+    pub fn branch(&self, node: usize) -> Option<(Edge, Edge)> {
+        let edges = &self.nodes[node].edges;
+        let taken = edges.iter().find(|e| e.kind == EdgeKind::CondTaken)?;
+        let not_taken = edges.iter().find(|e| e.kind == EdgeKind::CondNotTaken)?;
+        Some((*taken, *not_taken))
+    }
+
+    /// Drops the nodes that cannot be reached from the entry node 0, such as
+    /// the `goto` a compiler emits after a `return`. The exit node is kept.
+    fn prune_unreachable(&mut self) {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            if !reachable[n] {
+                reachable[n] = true;
+                stack.extend(self.successors(n));
             }
         }
-        result
-    }
-    
-    pub fn dominate_nodes(&self) -> Vec<Vec<usize>> {
-        let mut result = Vec::new();
-        result.push(iter::repeat(0).take(self.nodes.len()).collect::<Vec<usize>>());
-        result[0][0] = 1;
-        for n in 1..self.nodes.len() {
-           result.push(iter::repeat(1).take(self.nodes.len()).collect::<Vec<usize>>());
+        reachable[self.exit] = true;
+        let mut renumbered = vec![0; self.nodes.len()];
+        let mut count = 0;
+        for (i, &r) in reachable.iter().enumerate() {
+            renumbered[i] = count;
+            if r {
+                count += 1;
+            }
         }
-        loop {
-            let mut changed = false;
-            for n in 1..self.nodes.len() {
-                let mut t = iter::repeat(1).take(self.nodes.len()).collect::<Vec<usize>>();
-                for k in self.pred(n) {
-                    t = intersect(&result[k], &t);
-                }
-                t[n] = 1;
-                for j in 0..t.len() {
-                    if t[j] != result[n][j] {
-                        result[n] = t;
-                        changed = true;
-                        break;
+        let nodes = mem::take(&mut self.nodes);
+        self.preds = vec![Vec::new(); count];
+        for (i, mut node) in nodes.into_iter().enumerate() {
+            if reachable[i] {
+                node.index = renumbered[i];
+                for e in node.edges.iter_mut() {
+                    e.to = renumbered[e.to];
+                    if !self.preds[e.to].contains(&node.index) {
+                        self.preds[e.to].push(node.index);
                     }
                 }
-            }
-            if !changed {
-                break;
+                self.nodes.push(node);
             }
         }
-        result
+        self.exit = renumbered[self.exit];
     }
 
-    pub fn idom_nodes(&self, dom: &Vec<Vec<usize>>) -> Vec<usize> {
-        let mut tmp : Vec<Vec<usize>> = iter::repeat(iter::repeat(0).take(self.nodes.len()).collect::<Vec<usize>>()).take(self.nodes.len()).collect();
-        let mut idom = iter::repeat(0).take(self.nodes.len()).collect::<Vec<usize>>();
+    /// The dominator tree rooted at the entry node 0.
+    pub fn dominator_tree(&self) -> DominatorTree {
+        let succs: Vec<Vec<usize>> = (0..self.nodes.len()).map(|n| self.successors(n).collect()).collect();
+        DominatorTree::new(&succs, &self.preds, 0)
+    }
 
-        for i in 0..self.nodes.len() {
-            tmp[i] = dom[i].clone();
-            tmp[i][i] = 0;
+    /// The post-dominator tree, rooted at a virtual exit numbered
+    /// `nodes.len()`. The exit node leads to it, and so does every node from
+    /// which the exit node cannot be reached, such as an endless loop, so
+    /// that every node is post-dominated by the virtual exit.
+    pub fn post_dominator_tree(&self) -> DominatorTree {
+        let virtual_exit = self.nodes.len();
+        let mut reaches_exit = vec![false; virtual_exit];
+        let mut stack = vec![self.exit];
+        while let Some(n) = stack.pop() {
+            if !reaches_exit[n] {
+                reaches_exit[n] = true;
+                stack.extend(self.pred(n));
+            }
         }
-        for i in 1..self.nodes.len() {
-            for s in 0..self.nodes.len() {
-                if tmp[i][s] == 1 {
-                    for t in 0..self.nodes.len() {
-                        if t != s && tmp[s][t] == 1 {
-                            tmp[i][t] = 0;
-                        }
-                    }
-                }
+        // Reversed edges: a node's successors are its predecessors.
+        let mut succs: Vec<Vec<usize>> = self.preds.clone();
+        let mut preds: Vec<Vec<usize>> = (0..virtual_exit).map(|n| self.successors(n).collect()).collect();
+        succs.push(Vec::new());
+        preds.push(Vec::new());
+        for n in 0..virtual_exit {
+            if n == self.exit || !reaches_exit[n] {
+                succs[virtual_exit].push(n);
+                preds[n].push(virtual_exit);
             }
         }
+        DominatorTree::new(&succs, &preds, virtual_exit)
+    }
+
+    /// The immediate post-dominator of `n`, or `None` if only the virtual
+    /// exit post-dominates it.
+    fn ipdom(&self, pdoms: &DominatorTree, n: usize) -> Option<usize> {
+        pdoms.idom(n).filter(|&m| m < self.nodes.len())
+    }
 
-        for n in 1..self.nodes.len() {
-            idom[n] = tmp[n].iter().position(|k| *k == 1).unwrap();
+    /// For every node, the branches that decide whether it runs: node `y`
+    /// depends on the edge of `x` after which `y` is bound to run, when
+    /// `x` has another edge after which it may not.
+    pub fn control_dependence(&self) -> ControlDependence {
+        let pdoms = self.post_dominator_tree();
+        let mut deps = vec![Vec::new(); self.nodes.len()];
+        for (y, dep) in deps.iter_mut().enumerate() {
+            for &x in pdoms.frontier(y).iter().filter(|&&x| x < self.nodes.len()) {
+                for e in self.nodes[x].edges.iter().filter(|e| pdoms.dominates(y, e.to)) {
+                    dep.push((x, e.kind));
+                }
+            }
         }
-        idom
+        ControlDependence { deps }
     }
 
-    pub fn write_graphviz(&self, w: &mut Write) {
-        let d = self.dominate_nodes();
-        let id = self.idom_nodes(&d);
-        let lhs = self.loop_headers(&d);
-        w.write(b"digraph G {\n").unwrap();
-        for i in 0..self.nodes.len() {
-            if self.nodes[i].neighbors.is_empty() && self.pred(i).is_empty() {
+    /// The natural loops of the graph and how they nest.
+    pub fn loop_forest(&self) -> LoopForest {
+        let succs: Vec<Vec<usize>> = (0..self.nodes.len()).map(|n| self.successors(n).collect()).collect();
+        LoopForest::new(&succs, &self.preds, &self.dominator_tree())
+    }
+
+    /// Writes the graph as a DOT digraph called `name`.
+    pub fn write_graphviz(&self, name: &str, w: &mut dyn Write) -> io::Result<()> {
+        let dt = self.dominator_tree();
+        let pdt = self.post_dominator_tree();
+        let loops = self.loop_forest();
+        writeln!(w, "digraph \"{}\" {{", name)?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.edges.is_empty() && self.pred(i).is_empty() {
                 continue;
             }
-            let s = self.nodes[i].commands.iter()
+            let s = node.commands.iter()
                 .map(|s| format!("{}", s))
                 .collect::<Vec<String>>()
                 .join("\\n");
-            let mut doms = String::new();
-            for dn in 0..d[i].len() {
-                if d[i][dn] == 1 {
-                    doms = format!("{} {}", doms, dn);
-                }
-            }
+            let mut doms: Vec<usize> = dt.dominators(i);
+            doms.sort_unstable();
+            let doms: String = doms.iter().map(|d| format!(" {}", d)).collect();
             w.write_fmt(format_args!(
-                "{} [shape=box,label=\"{}\\n{}\\nlabel={}\\ndoms{{{}}}\\nidom={}\\n{}\"];\n",
+                "{} [shape=box,label=\"{}\\n{}\\nlabel={}\\ndoms{{{}}}\\nidom={}\\nipdom={}\\n{}\"];\n",
                 i,
-                if lhs[i] == 1 { "header" } else { "" },
+                if loops.is_header(i) { "header" } else { "" },
                 i,
-                self.nodes[i].label.as_ref().unwrap_or(&"".into()),
+                node.label.as_ref().unwrap_or(&"".into()),
                 doms,
-                id[i],
+                dt.idom(i).unwrap_or(0),
+                self.ipdom(&pdt, i).map_or("-".into(), |m| m.to_string()),
                 s
-            )).unwrap();
-            for n in self.nodes[i].neighbors.iter() {
-                w.write_fmt(format_args!(
-                    "{}->{}[label=\"{}\"];\n",
-                    i,
-                    n,
-                    self.edge_label.get(&(i, *n)).unwrap_or(&"")
-                )).unwrap();
+            ))?;
+            for e in node.edges.iter() {
+                w.write_fmt(format_args!("{}->{}[label=\"{}\"];\n", i, e.to, e.kind))?;
             }
         }
-        w.write(b"}\n").unwrap();
+        w.write_all(b"}\n")
     }
 }
 
-impl Graph<VmCommand> {
-    pub fn build(commands: Vec<VmCommand>) -> Graph<VmCommand> {
-        let block = BasicBlock::default();
+/// How a basic block hands control on once its commands have run.
+enum Terminator {
+    FallThrough,
+    Goto(String),
+    IfGoto(String),
+    Return,
+}
+
+impl Graph<Spanned<VmCommand>> {
+    /// Builds the control-flow graph of one function body in two passes.
+    /// The first splits the body into basic blocks at leaders: the first
+    /// command, every label, and every command following a `goto`, `if-goto`
+    /// or `return`. The second wires each block to its successors. Blocks
+    /// ending in `return` lead to a single empty exit node, and blocks that
+    /// cannot be reached from the entry are dropped.
+    pub fn build(commands: Vec<Spanned<VmCommand>>) -> Result<Graph<Spanned<VmCommand>>, CfgError> {
         let mut graph = Graph {
             nodes: Vec::new(),
-            edge_label: HashMap::new(),
+            preds: Vec::new(),
+            exit: 0,
         };
-        let mut index = graph.add_node(block);
+        let mut labels = HashMap::new();
+        let mut terminators = Vec::new();
+        let mut jumps = Vec::new();
+        let mut current: Option<usize> = None;
         for c in commands {
-            match c {
-                VmCommand::Goto(label) => {
-                    if let Some(n) = graph.find_node_by_label(&label) {
-                        graph.nodes[index].neighbors.push(n);
-                        graph.edge_label.insert((index, n), "goto");
-                    } else if graph.nodes[index].is_empty() {
-                        graph.nodes[index].label = Some(label);
+            let index = match current {
+                Some(index) => index,
+                None => {
+                    terminators.push(Terminator::FallThrough);
+                    graph.add_node(BasicBlock::default())
+                }
+            };
+            current = Some(index);
+            match c.node {
+                VmCommand::Label(label) => {
+                    let block = &graph.nodes[index];
+                    let index = if block.label.is_none() && block.commands.is_empty() {
+                        index
                     } else {
-                        let dummy_block = BasicBlock {
-                            label: Some(label),
-                            ..Default::default()
-                        };
-                        let new_index = graph.add_node(dummy_block);
-                        graph.nodes[index].neighbors.push(new_index);
-                        graph.edge_label.insert((index, new_index), "goto");
+                        terminators.push(Terminator::FallThrough);
+                        graph.add_node(BasicBlock::default())
+                    };
+                    if labels.insert(label.clone(), index).is_some() {
+                        return Err(CfgError::DuplicateLabel(label, c.span));
                     }
-                    index = graph.add_node(BasicBlock::default());
-                }
-                VmCommand::Label(label) => if let Some(n) = graph.find_node_by_label(&label) {
-                    graph.nodes[index].neighbors.push(n);
-                    index = n;
-                } else if graph.nodes[index].is_empty() {
                     graph.nodes[index].label = Some(label);
-                } else {
-                    let block = BasicBlock {
-                        label: Some(label),
-                        ..Default::default()
-                    };
-                    let new_index = graph.add_node(block);
-                    graph.nodes[index].neighbors.push(new_index);
-                    index = new_index;
-                },
+                    current = Some(index);
+                }
+                VmCommand::Goto(label) => {
+                    jumps.push((label.clone(), c.span.clone()));
+                    graph.nodes[index].end = Some(c.span);
+                    terminators[index] = Terminator::Goto(label);
+                    current = None;
+                }
                 VmCommand::IfGoto(label) => {
-                    if let Some(n) = graph.find_node_by_label(&label) {
-                        graph.nodes[index].neighbors.push(n);
-                        graph.edge_label.insert((index, n), "if-goto");
-                    } else {
-                        let block = BasicBlock {
-                            label: Some(label),
-                            ..Default::default()
-                        };
-                        let new_index = graph.add_node(block);
-                        graph.nodes[index].neighbors.push(new_index);
-                        graph.edge_label.insert((index, new_index), "if-goto");
+                    jumps.push((label.clone(), c.span.clone()));
+                    graph.nodes[index].end = Some(c.span);
+                    terminators[index] = Terminator::IfGoto(label);
+                    current = None;
+                }
+                VmCommand::Return => {
+                    graph.nodes[index].end = Some(c.span.clone());
+                    graph.nodes[index].commands.push(Spanned { node: VmCommand::Return, span: c.span });
+                    terminators[index] = Terminator::Return;
+                    current = None;
+                }
+                cmd => graph.nodes[index].commands.push(Spanned { node: cmd, span: c.span }),
+            }
+        }
+        if let Some((label, span)) = jumps.into_iter().find(|(l, _)| !labels.contains_key(l)) {
+            return Err(CfgError::UndefinedLabel(label, span));
+        }
+
+        graph.exit = graph.add_node(BasicBlock::default());
+        for (index, terminator) in terminators.into_iter().enumerate() {
+            let next = index + 1;
+            match terminator {
+                Terminator::FallThrough => if next < graph.exit {
+                    graph.add_edge(index, next, EdgeKind::FallThrough);
+                },
+                Terminator::Goto(label) => graph.add_edge(index, labels[&label], EdgeKind::Goto),
+                Terminator::IfGoto(label) => {
+                    graph.add_edge(index, labels[&label], EdgeKind::CondTaken);
+                    if next < graph.exit {
+                        graph.add_edge(index, next, EdgeKind::CondNotTaken);
                     }
-                    let not_taken_index = graph.add_node(BasicBlock::default());
-                    graph.nodes[index].neighbors.push(not_taken_index);
-                    index = not_taken_index;
                 }
-                cmd => graph.nodes[index].commands.push(cmd),
+                Terminator::Return => {
+                    let exit = graph.exit;
+                    graph.add_edge(index, exit, EdgeKind::Return);
+                }
             }
         }
-        graph.shrink();
-        graph
+        graph.prune_unreachable();
+        Ok(graph)
     }
 }
 
+/// What the structurer knows about the shape of a function's graph.
+struct Regions {
+    pdoms: DominatorTree,
+    loops: LoopForest,
+}
+
+/// The statements to emit when structuring reaches a node that ends the
+/// current region, or `None` if the node lies inside it.
+type Stop<'a> = &'a dyn Fn(usize) -> Option<Vec<UnTypedIR>>;
+
 impl Graph<UnTypedIR> {
-    pub fn reconstruct_code(&mut self) -> Vec<UnTypedIR> {
-        let doms = self.dominate_nodes();
-        let idoms = self.idom_nodes(&doms);
-        let lhs = self.loop_headers(&doms);
+    pub fn reconstruct_code(&mut self) -> Result<Vec<UnTypedIR>, CfgError> {
+        let regions = Regions {
+            pdoms: self.post_dominator_tree(),
+            loops: self.loop_forest(),
+        };
         for n in self.nodes.iter_mut() {
             let t = n.commands.drain(0..).collect();
             n.commands = recover_array_access(t);
         }
-        let (_, irs) = self.reconstruct_from_node_until(0, &doms, &idoms, &lhs, &|_| false);
-        irs
+        if !regions.loops.irreducible_edges().is_empty() {
+            let mut entries: Vec<usize> = regions.loops.irreducible_edges().iter()
+                .flat_map(|&(from, to)| vec![from, to])
+                .collect();
+            entries.sort_unstable();
+            entries.dedup();
+            return Err(CfgError::Irreducible(entries.into_iter().map(|n| match self.nodes[n].label {
+                Some(ref label) => label.clone(),
+                None => format!("block {}", n),
+            }).collect()));
+        }
+        let (_, irs) = self.reconstruct_from_node_until(0, &regions, &|_| None)?;
+        Ok(irs)
     }
 
-    fn reconstruct_from_node_until(&self, n: usize, doms: &Vec<Vec<usize>>, idoms: &Vec<usize>, lhs: &Vec<usize>, should_return: &Fn(usize) -> bool) -> (Option<usize>, Vec<UnTypedIR>) {
-        if should_return(n) {
-            return (Some(n), vec![]);
+    /// The fallback for graphs `reconstruct_code` cannot structure: a loop
+    /// that runs the block numbered `STATE` and sets `STATE` to the block to
+    /// run next, until a block returns or control runs off the end, which
+    /// sets `STATE` to the number of the exit.
+    pub fn state_machine(&self) -> Result<Vec<UnTypedIR>, CfgError> {
+        let state = UnTypedIR::Var("STATE".into());
+        let goto = |n: usize| vec![UnTypedIR::Assign(Box::new(state.clone()), Box::new(UnTypedIR::ConstInt(n as i32)))];
+        let mut blocks = Vec::new();
+        for n in (0..self.nodes.len()).filter(|&n| n != self.exit) {
+            let mut commands = self.nodes[n].commands.clone();
+            if self.nodes[n].edges.len() == 2 {
+                let (t, nt, cond) = self.split_branch(n, &mut commands)?;
+                commands.push(with_span_of(cond, |cond| UnTypedIR::If(Box::new(cond), goto(t), goto(nt), vec![])));
+            } else if let Some(next) = self.successors(n).next().filter(|&next| next != self.exit) {
+                commands.extend(goto(next));
+            } else if self.nodes[n].edges.is_empty() {
+                // Control runs off the end of the function, so the machine
+                // stops.
+                commands.extend(goto(self.exit));
+            }
+            let is_current = UnTypedIR::Binary("=".into(), Box::new(state.clone()), Box::new(UnTypedIR::ConstInt(n as i32)));
+            blocks.push(UnTypedIR::If(Box::new(is_current), commands, vec![], vec![]));
         }
-        let mut result = self.nodes[n].commands.clone();
-        if self.nodes[n].neighbors.is_empty() {
-            return (None, result);
+        let done = UnTypedIR::Binary("=".into(), Box::new(state.clone()), Box::new(UnTypedIR::ConstInt(self.exit as i32)));
+        let mut result = goto(0);
+        result.push(UnTypedIR::While(Box::new(negate(done)), blocks, vec![]));
+        Ok(result)
+    }
+
+    /// The taken and not-taken successors of the branch `n`, together with
+    /// the condition that ends its commands. The condition's span takes in
+    /// the `if-goto` that tests it.
+    fn split_branch(&self, n: usize, commands: &mut Vec<UnTypedIR>) -> Result<(usize, usize, UnTypedIR), CfgError> {
+        let malformed = || CfgError::MalformedBranch(n, self.nodes[n].label.clone());
+        let (t, nt) = self.branch(n).ok_or_else(malformed)?;
+        let cond = match (commands.pop().ok_or_else(malformed)?, self.nodes[n].end.as_ref()) {
+            (UnTypedIR::Spanned(span, cond), Some(end)) => UnTypedIR::Spanned(span.merge(end), cond),
+            (cond, _) => cond,
+        };
+        Ok((t.to, nt.to, cond))
+    }
+
+    /// Structures the code from `n` until control leaves the function or
+    /// reaches a node for which `stop` has statements. Returns that node
+    /// along with the code.
+    fn reconstruct_from_node_until(&self, n: usize, regions: &Regions, stop: Stop) -> Result<(Option<usize>, Vec<UnTypedIR>), CfgError> {
+        if n == self.exit {
+            return Ok((None, vec![]));
         }
-        if lhs[n] == 1 {
-            let t= self.taken_edge(n);
-            let nt = self.not_taken_edge(n);
-            let last_expr = result.pop().unwrap();
-            let (_, loop_body) = self.reconstruct_from_node_until(nt, doms, idoms, lhs, &|i| i == n);
-            let (_, continuation) = self.reconstruct_from_node_until(t, doms, idoms, lhs, should_return);
-            let expr = UnTypedIR::While(Box::new(last_expr), loop_body, continuation);
-            result.push(expr);
-            return (None, result);
+        if let Some(stmts) = stop(n) {
+            return Ok((Some(n), stmts));
         }
-        if self.nodes[n].neighbors.len() == 2 {
-            let t = self.taken_edge(n);
-            let nt = self.not_taken_edge(n);
-            let last_expr = result.pop().unwrap();
-            let mut else_body = vec![];
-            let (n1, if_body) = self.reconstruct_from_node_until(t, doms, idoms, lhs, &|i| doms[i][t] == 0);
-            let mut contn = nt;
-            if self.pred(nt).len() == 1 {
-                let (n2, eb) = self.reconstruct_from_node_until(nt, doms, idoms, lhs, &|i| doms[i][nt] == 0);
-                else_body = eb;
-                if n1.is_some() && n2.is_some() {
-                    let contn1 = n1.unwrap();
-                    let contn2 = n2.unwrap();
-
-                    if contn1 != contn2 {
-                        panic!("If statements not reach same node if {} vs else {}", contn1, contn2);
-                    }
-                    contn = contn2;
-                } 
-            }
-            let (r, rs) = self.reconstruct_from_node_until(contn, doms, idoms, lhs, should_return);
-            let expr = UnTypedIR::If(Box::new(last_expr), if_body, else_body, rs);
+        match regions.loops.loop_at(n) {
+            Some(l) => self.reconstruct_loop(l, regions, stop),
+            None => self.reconstruct_block(n, regions, stop),
+        }
+    }
+
+    /// Structures the block `n` and what follows it, treating `n` as an
+    /// ordinary block even if it heads a loop.
+    fn reconstruct_block(&self, n: usize, regions: &Regions, stop: Stop) -> Result<(Option<usize>, Vec<UnTypedIR>), CfgError> {
+        let mut result = self.nodes[n].commands.clone();
+        if self.nodes[n].edges.is_empty() {
+            return Ok((None, result));
+        }
+        if self.nodes[n].edges.len() == 2 {
+            // Both arms run until the branch's immediate post-dominator,
+            // where control is certain to meet again. If the region ends
+            // there, each arm ends the region on its own.
+            let (t, nt, last_expr) = self.split_branch(n, &mut result)?;
+            let merge = match self.ipdom(&regions.pdoms, n) {
+                Some(m) if m != self.exit => Some(m).filter(|&m| stop(m).is_none()),
+                _ => self.follow_returning(t, nt, stop),
+            };
+            let until = |i| if Some(i) == merge { Some(vec![]) } else { stop(i) };
+            let (n1, if_body) = self.reconstruct_from_node_until(t, regions, &until)?;
+            let (n2, else_body) = self.reconstruct_from_node_until(nt, regions, &until)?;
+            let (r, rs) = match merge {
+                Some(merge) => self.reconstruct_from_node_until(merge, regions, stop)?,
+                None => (n1.or(n2), vec![]),
+            };
+            let expr = with_span_of(last_expr, |cond| if if_body.is_empty() && !else_body.is_empty() {
+                // Only the not-taken arm does anything, as where a loop's
+                // latch leaves the loop.
+                UnTypedIR::If(Box::new(negate(cond)), else_body, vec![], rs)
+            } else {
+                UnTypedIR::If(Box::new(cond), if_body, else_body, rs)
+            });
             result.push(expr);
-            return (r, result);
+            return Ok((r, result));
         }
         let mut ret = None;
-        for i in self.nodes[n].neighbors.iter() {
-            let (r, rs) = self.reconstruct_from_node_until(*i, doms, idoms, lhs, should_return);
+        for i in self.successors(n) {
+            let (r, rs) = self.reconstruct_from_node_until(i, regions, stop)?;
             ret = r;
             result.extend(rs);
         }
-        (ret, result)
+        Ok((ret, result))
     }
 
-    fn taken_edge(&self, n: usize) -> usize {
-        for i in self.nodes[n].neighbors.iter() {
-            if self.edge_label.get(&(n, *i)).is_some() && *self.edge_label.get(&(n, *i)).unwrap() == "if-goto" {
-                return *i;
+    /// The nodes reachable from `from` without passing `exit`, the node
+    /// `blocked` or a node `stop` ends the region at, and whether such a
+    /// node was met. `blocked` itself counts as reachable.
+    fn reach(&self, from: usize, blocked: Option<usize>, stop: Stop) -> (Vec<bool>, bool) {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut meets_stop = false;
+        let mut stack = vec![from];
+        while let Some(n) = stack.pop() {
+            if n == self.exit || seen[n] {
+                continue;
+            }
+            if stop(n).is_some() {
+                meets_stop = true;
+                continue;
+            }
+            seen[n] = true;
+            if Some(n) != blocked {
+                stack.extend(self.successors(n));
             }
         }
-        panic!("cannot find taken edge");
+        (seen, meets_stop)
     }
 
-    fn not_taken_edge(&self, n: usize) -> usize {
-        for i in self.nodes[n].neighbors.iter() {
-            if self.edge_label.get(&(n, *i)).is_none() || *self.edge_label.get(&(n, *i)).unwrap() != "if-goto" {
-                return *i;
+    /// Where the arms `t` and `nt` of a branch meet when only the exit
+    /// node post-dominates it because some paths return. Returning paths end
+    /// on their own, so the arms meet at the first node every other path
+    /// passes through. When no path of an arm gets anywhere but the exit,
+    /// the other arm is the code after the branch; if both arms only
+    /// return, the later one in the source is.
+    fn follow_returning(&self, t: usize, nt: usize, stop: Stop) -> Option<usize> {
+        let ((from_t, t_stops), (from_nt, nt_stops)) = (self.reach(t, None, stop), self.reach(nt, None, stop));
+        let common: Vec<usize> = (0..self.nodes.len()).filter(|&m| from_t[m] && from_nt[m]).collect();
+        if common.is_empty() {
+            let returns_only = |from: &[bool], stops: bool| !stops && from.iter().any(|&r| r);
+            return match (returns_only(&from_t, t_stops), returns_only(&from_nt, nt_stops)) {
+                (true, true) => Some(t.max(nt)),
+                (true, false) => Some(nt).filter(|_| from_nt[nt]),
+                (false, true) => Some(t).filter(|_| from_t[t]),
+                (false, false) => None,
+            };
+        }
+        common.iter().cloned().find(|&m| {
+            let (after, _) = self.reach(m, None, stop);
+            let (before_t, _) = self.reach(t, Some(m), stop);
+            let (before_nt, _) = self.reach(nt, Some(m), stop);
+            common.iter().all(|&c| after[c]) &&
+                (0..self.nodes.len()).all(|x| x == m || !after[x] || !(before_t[x] || before_nt[x]))
+        })
+    }
+
+    /// The node where the code after the loop `l` starts, if control can
+    /// get there. Exits into code that can only return, such as a `return`
+    /// inside the loop body, stay part of the body and do not count, as long
+    /// as no other exit leads into the same code.
+    fn loop_follow(&self, l: &Loop) -> Result<Option<usize>, CfgError> {
+        let mut targets: Vec<usize> = l.exits.iter().map(|&(_, to)| to).filter(|&to| to != self.exit).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        // The code each exit leads to, or `None` if it leads back into the
+        // loop.
+        let regions: Vec<Option<Vec<bool>>> = targets.iter().map(|&t| {
+            let mut seen = vec![false; self.nodes.len()];
+            let mut stack = vec![t];
+            while let Some(n) = stack.pop() {
+                if n == self.exit || seen[n] {
+                    continue;
+                }
+                if l.contains(n) {
+                    return None;
+                }
+                seen[n] = true;
+                stack.extend(self.successors(n));
             }
+            Some(seen)
+        }).collect();
+        let returns_only = |i: usize| match regions[i] {
+            Some(ref region) => regions.iter().enumerate().all(|(j, other)| {
+                i == j || other.as_ref().is_none_or(|other| !region.iter().zip(other).any(|(&a, &b)| a && b))
+            }),
+            None => false,
+        };
+        let header_exit = self.branch(l.header)
+            .map(|(t, nt)| if l.contains(t.to) { nt.to } else { t.to })
+            .filter(|to| targets.contains(to));
+        let continuing: Vec<usize> = (0..targets.len()).filter(|&i| !returns_only(i)).map(|i| targets[i]).collect();
+        match (header_exit, continuing.len()) {
+            (Some(to), 0) => Ok(Some(to)),
+            (Some(to), 1) if continuing[0] == to => Ok(Some(to)),
+            (None, 1) => Ok(Some(continuing[0])),
+            // Every exit returns; take the one latest in the source.
+            (None, 0) => Ok(targets.last().cloned()),
+            _ => Err(CfgError::UnstructuredLoop(l.header, self.nodes[l.header].label.clone())),
         }
-        panic!("cannot find not taken edge");
+    }
+
+    /// Structures the loop `l` as a `while` statement followed by the code
+    /// after the loop. A loop whose header only tests the exit condition
+    /// becomes `while (cond) { body }`. Any other loop with a single exit,
+    /// such as one tested at its latch, is driven by a flag instead:
+    /// `LOOP_h` is set before the loop and cleared where the loop exits.
+    /// A loop that never exits becomes `while (~(0))`.
+    fn reconstruct_loop(&self, l: &Loop, regions: &Regions, stop: Stop) -> Result<(Option<usize>, Vec<UnTypedIR>), CfgError> {
+        let h = l.header;
+        let target = self.loop_follow(l)?;
+        let (r, rest) = match target {
+            Some(target) => self.reconstruct_from_node_until(target, regions, stop)?,
+            None => (None, vec![]),
+        };
+
+        let header_tested = l.exits.iter().all(|&(from, to)| from == h || Some(to) != target) &&
+            self.nodes[h].commands.len() == 1 && self.branch(h).is_some();
+        let mut result = vec![];
+        if header_tested {
+            let mut commands = self.nodes[h].commands.clone();
+            let (t, nt, last_expr) = self.split_branch(h, &mut commands)?;
+            let (inside, stays) = if l.contains(nt) { (nt, false) } else { (t, true) };
+            let (_, body) = self.reconstruct_from_node_until(inside, regions, &|i| if i == h { Some(vec![]) } else { None })?;
+            // The loop runs on while the branch stays inside.
+            result.push(with_span_of(last_expr, |cond| {
+                UnTypedIR::While(Box::new(if stays { cond } else { negate(cond) }), body, rest)
+            }));
+        } else if target.is_none() {
+            let (_, body) = self.reconstruct_block(h, regions, &|i| if i == h { Some(vec![]) } else { None })?;
+            result.push(UnTypedIR::While(Box::new(negate(UnTypedIR::ConstInt(0))), body, rest));
+        } else {
+            let flag = UnTypedIR::Var(format!("LOOP_{}", h));
+            let assign = |value| UnTypedIR::Assign(Box::new(flag.clone()), Box::new(value));
+            let (_, body) = self.reconstruct_block(h, regions, &|i| {
+                if i == h {
+                    Some(vec![])
+                } else if Some(i) == target {
+                    Some(vec![assign(UnTypedIR::ConstInt(0))])
+                } else {
+                    None
+                }
+            })?;
+            result.push(assign(negate(UnTypedIR::ConstInt(0))));
+            result.push(UnTypedIR::While(Box::new(flag), body, rest));
+        }
+        Ok((r, result))
     }
 }
 
-fn intersect(v1: &[usize], v2: &[usize]) -> Vec<usize> {
-    let mut result = Vec::new();
-    for i in 0..v1.len() {
-        result.push(v1[i] * v2[i]);
+/// The logical negation of the condition `cond`, without doubling `~`.
+fn negate(cond: UnTypedIR) -> UnTypedIR {
+    match cond {
+        UnTypedIR::Unary(op, e) => if op == "~" {
+            *e
+        } else {
+            UnTypedIR::Unary("~".into(), Box::new(UnTypedIR::Unary(op, e)))
+        },
+        cond => UnTypedIR::Unary("~".into(), Box::new(cond)),
     }
-    result
 }
 
-pub fn recover_array_access(mut irs: Vec<UnTypedIR>) -> Vec<UnTypedIR> {
-    return irs;
-    loop {
-        let mut last_def_index = None;
-        let mut last_use_index = None;
-        for i in 0..irs.len() {
-            if irs[i].has_use("THAT_0") {
-                last_use_index = Some(i);
-                break;
-            } else {
-                match &irs[i] {
-                    &UnTypedIR::Assign(ref s, _) if s.is_assigned_to("POINTER_1") => { println!("assigned to {:?}", irs[i]); last_def_index = Some(i) },
-                    _ => (),
-                }
+/// Builds a statement around the condition `cond`, moving the condition's
+/// span onto the statement.
+fn with_span_of<F: FnOnce(UnTypedIR) -> UnTypedIR>(cond: UnTypedIR, build: F) -> UnTypedIR {
+    match cond {
+        UnTypedIR::Spanned(span, cond) => UnTypedIR::Spanned(span, Box::new(build(*cond))),
+        cond => build(cond),
+    }
+}
+
+/// Finishes array writes in a block's statements. The lifter already turns
+/// accesses through `pointer 1` into `a[i]`, which leaves the `temp 0` swap
+/// Jack compilers use for `let a[i] = e;`:
+///
+/// ```text
+/// let TEMP_0 = e;
+/// let a[i] = TEMP_0;
+/// ```
+///
+/// This becomes `let a[i] = e;` unless the block reads `TEMP_0` again
+/// before assigning it.
+pub fn recover_array_access(irs: Vec<UnTypedIR>) -> Vec<UnTypedIR> {
+    let temp = UnTypedIR::Var("TEMP_0".into());
+    let mut result: Vec<UnTypedIR> = Vec::new();
+    for (i, ir) in irs.iter().enumerate() {
+        let write = match (result.last().map(UnTypedIR::unspanned), ir.unspanned()) {
+            (Some(UnTypedIR::Assign(t, value)), UnTypedIR::Assign(element, read))
+                if **t == temp && **read == temp && !element.has_use("TEMP_0") &&
+                    matches!(**element, UnTypedIR::ArrayOffset(..)) &&
+                    !read_before_assigned(&irs[i + 1..], "TEMP_0") => {
+                Some(UnTypedIR::Assign(element.clone(), value.clone()))
             }
-        }
-        if last_use_index.is_some() {
-            let use_index = last_use_index.take().unwrap();
-            let def_index = last_def_index.take().unwrap();
-            let ir = irs.remove(use_index);
-            for r in irs.iter() {
-                println!("{}", r);
+            _ => None,
+        };
+        match write {
+            Some(write) => {
+                let swap = result.pop().unwrap();
+                result.push(match (swap.span(), ir.span()) {
+                    (Some(a), Some(b)) => UnTypedIR::Spanned(a.merge(b), Box::new(write)),
+                    _ => write,
+                });
             }
-            let def_ir = irs.remove(def_index);
-            println!("rm'd");
-            for r in irs.iter() {
-                println!("{}", r);
+            None => result.push(ir.clone()),
+        }
+    }
+    result
+}
+
+/// Whether a statement of `irs` reads `var` before one assigns it.
+fn read_before_assigned(irs: &[UnTypedIR], var: &str) -> bool {
+    for ir in irs {
+        match ir.unspanned() {
+            UnTypedIR::Assign(target, value) => {
+                if value.has_use(var) || (**target != UnTypedIR::Var(var.into()) && target.has_use(var)) {
+                    return true;
+                }
+                if **target == UnTypedIR::Var(var.into()) {
+                    return false;
+                }
             }
-            println!("end rm'd");
-            irs.insert(use_index - 1, ir.replace_var("THAT_0", &def_ir.to_array_offset()));
-        } else {
-            break;
+            ir => if ir.has_use(var) {
+                return true;
+            },
         }
     }
-    irs
+    false
+}
+
+/// Builds the control-flow graph of one function.
+pub fn function_graph(function: &Function) -> Result<Graph<Spanned<VmCommand>>, CfgError> {
+    Graph::build(function.body.clone())
+}
+
+fn decompile_function(class: &str, function: &Function, params: usize, diags: &mut Vec<Diagnostic>) -> Result<UnTypedIR, CfgError> {
+    let vm = function_graph(function)?;
+    let errors = diags.len();
+    let heights = stack::verify(&vm, diags);
+    if diags.len() > errors {
+        return Err(CfgError::UnbalancedStack(function.name.clone()));
+    }
+    let mut g = Graph::from_vm(vm, &heights, class);
+    let rs = match g.reconstruct_code() {
+        Err(e @ CfgError::Irreducible(_)) | Err(e @ CfgError::UnstructuredLoop(..)) => {
+            diags.push(Diagnostic::warning(
+                Some(function.span.clone()),
+                format!("{}: {}; decompiled as a state machine", function.name, e),
+            ));
+            g.state_machine()?
+        }
+        rs => rs?,
+    };
+    let sig = Signature::function(function.name.clone(), params, function.locals.max(0) as usize);
+    Ok(UnTypedIR::FuncDef(sig, rs))
 }
 
-pub fn to_untyped_ir(vm_commands: &mut Iterator<Item=VmCommand>) -> Vec<UnTypedIR> {
-    let mut buffer = Vec::new();
-    let mut current_func = String::new();
+/// Decompiles every function of `program`, keeping them grouped by the
+/// class file that defines them. Functions that could only be decompiled
+/// in a degraded form add a warning to `diags`; functions that cannot be
+/// decompiled at all add an error and are left out.
+pub fn to_untyped_ir(program: &Program, diags: &mut Vec<Diagnostic>) -> Vec<UnTypedClass> {
+    let params = params::infer(program, diags);
     let mut result = Vec::new();
-    for c in vm_commands {
-        match c {
-            VmCommand::FunDef(s, _)  => {
-                if buffer.is_empty() {
-                    current_func = s;
-                    buffer = vec![];
-                } else {
-                    let mut g: Graph<UnTypedIR> = From::from(Graph::build(buffer.drain(0..).collect()));
-                    let rs = g.reconstruct_code();
-                    result.push(UnTypedIR::FuncDef(current_func, rs));
-                    current_func = s;
+    for class in program.classes.iter() {
+        let mut subroutines = Vec::new();
+        for f in class.functions.iter() {
+            match decompile_function(&class.name, f, params[&f.name], diags) {
+                Ok(s) => subroutines.push(s),
+                Err(e) => {
+                    // `stack::verify` has already said what is unbalanced.
+                    let reason = match e {
+                        CfgError::UnbalancedStack(_) => "its operand stack is not balanced".to_string(),
+                        e => e.to_string(),
+                    };
+                    diags.push(Diagnostic::error(Some(f.span.clone()), format!(
+                        "{} is left out: {}", f.name, reason,
+                    )));
                 }
             }
-            x => {
-                buffer.push(x);
-            }
         }
+        result.push(UnTypedClass { name: class.name.clone(), fields: 0, subroutines });
     }
+    resolve_void_returns(result)
+}
 
-    let mut g: Graph<UnTypedIR> = From::from(Graph::build(buffer.drain(0..).collect()));
-    let rs = g.reconstruct_code();
-    result.push(UnTypedIR::FuncDef(current_func, rs));
-    result
-}
\ No newline at end of file
+/// Choices about how far `decompile` rewrites the program.
+#[derive(Clone, Copy, Default)]
+pub struct Options {
+    /// Keep calls of `Math.multiply` and `Math.divide` instead of turning
+    /// them into `*` and `/`.
+    pub math_calls: bool,
+}
+
+/// Decompiles `program` into the IR every output format starts from:
+/// lifted, with constant strings, operators and objects recovered.
+pub fn decompile(program: &Program, options: Options, diags: &mut Vec<Diagnostic>) -> Vec<UnTypedClass> {
+    let classes = to_untyped_ir(program, diags).into_iter()
+        .map(|c| c.reconstruct_const_string())
+        .map(|c| if options.math_calls { c } else { c.recover_operators() })
+        .collect();
+    reconstruct_objects(classes)
+}
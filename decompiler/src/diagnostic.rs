@@ -0,0 +1,42 @@
+use std::fmt;
+
+use parser::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found while decompiling that does not stop the decompiler,
+/// such as code it had to restructure in an unusual way.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Where in the VM source the problem is, if it is tied to a place.
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn warning(span: Option<Span>, message: String) -> Self {
+        Diagnostic { severity: Severity::Warning, span, message }
+    }
+
+    pub fn error(span: Option<Span>, message: String) -> Self {
+        Diagnostic { severity: Severity::Error, span, message }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
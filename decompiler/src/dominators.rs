@@ -0,0 +1,174 @@
+/// The dominator tree of a graph given as successor and predecessor lists,
+/// built with the iterative algorithm of Cooper, Harvey and Kennedy. It
+/// takes O(n) memory and answers `dominates` in constant time by numbering
+/// the tree in depth-first order.
+pub struct DominatorTree {
+    root: usize,
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    frontier: Vec<Vec<usize>>,
+    /// Entry and exit times of each node in a walk of the tree; unreachable
+    /// nodes keep `usize::MAX`.
+    enter: Vec<usize>,
+    leave: Vec<usize>,
+}
+
+/// Nodes reachable from `root` in reverse post-order.
+fn reverse_post_order(succs: &[Vec<usize>], root: usize) -> Vec<usize> {
+    let mut visited = vec![false; succs.len()];
+    let mut order = Vec::new();
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+    while let Some(&mut (n, ref mut next)) = stack.last_mut() {
+        if let Some(&s) = succs[n].get(*next) {
+            *next += 1;
+            if !visited[s] {
+                visited[s] = true;
+                stack.push((s, 0));
+            }
+        } else {
+            order.push(n);
+            stack.pop();
+        }
+    }
+    order.reverse();
+    order
+}
+
+impl DominatorTree {
+    pub fn new(succs: &[Vec<usize>], preds: &[Vec<usize>], root: usize) -> Self {
+        let n = succs.len();
+        let rpo = reverse_post_order(succs, root);
+        let mut rank = vec![usize::MAX; n];
+        for (i, &b) in rpo.iter().enumerate() {
+            rank[b] = i;
+        }
+
+        let mut idom: Vec<Option<usize>> = vec![None; n];
+        idom[root] = Some(root);
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while rank[a] > rank[b] {
+                    a = idom[a].unwrap();
+                }
+                while rank[b] > rank[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &p in preds[b].iter().filter(|&&p| idom[p].is_some()) {
+                    new_idom = Some(match new_idom {
+                        None => p,
+                        Some(d) => intersect(&idom, p, d),
+                    });
+                }
+                if new_idom.is_some() && idom[b] != new_idom {
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[root] = None;
+
+        let mut children = vec![Vec::new(); n];
+        for &b in rpo.iter() {
+            if let Some(d) = idom[b] {
+                children[d].push(b);
+            }
+        }
+
+        // Only join points have frontiers to add; the root counts as one as
+        // soon as it has a predecessor, joining it with the function entry.
+        let mut frontier = vec![Vec::new(); n];
+        for &b in rpo.iter().filter(|&&b| preds[b].len() >= 2 || (b == root && !preds[b].is_empty())) {
+            for &p in preds[b].iter().filter(|&&p| rank[p] != usize::MAX) {
+                let mut runner = Some(p);
+                while runner.is_some() && runner != idom[b] {
+                    let r = runner.unwrap();
+                    if !frontier[r].contains(&b) {
+                        frontier[r].push(b);
+                    }
+                    runner = idom[r];
+                }
+            }
+        }
+
+        let mut tree = DominatorTree {
+            root,
+            idom,
+            children,
+            frontier,
+            enter: vec![usize::MAX; n],
+            leave: vec![usize::MAX; n],
+        };
+        tree.number();
+        tree
+    }
+
+    fn number(&mut self) {
+        let mut clock = 0;
+        let mut stack = vec![(self.root, 0)];
+        self.enter[self.root] = clock;
+        while let Some(&mut (n, ref mut next)) = stack.last_mut() {
+            clock += 1;
+            if let Some(&c) = self.children[n].get(*next) {
+                *next += 1;
+                self.enter[c] = clock;
+                stack.push((c, 0));
+            } else {
+                self.leave[n] = clock;
+                stack.pop();
+            }
+        }
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    pub fn is_reachable(&self, n: usize) -> bool {
+        self.enter[n] != usize::MAX
+    }
+
+    /// The immediate dominator of `n`; `None` for the root and for nodes
+    /// the root cannot reach.
+    pub fn idom(&self, n: usize) -> Option<usize> {
+        self.idom[n]
+    }
+
+    /// Whether every path from the root to `b` passes through `a`. A node
+    /// dominates itself.
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        self.is_reachable(a) && self.is_reachable(b) &&
+            self.enter[a] <= self.enter[b] && self.leave[b] <= self.leave[a]
+    }
+
+    /// The nodes `n` immediately dominates.
+    pub fn children(&self, n: usize) -> &[usize] {
+        &self.children[n]
+    }
+
+    /// The dominance frontier of `n`: the nodes where `n`'s dominance ends.
+    pub fn frontier(&self, n: usize) -> &[usize] {
+        &self.frontier[n]
+    }
+
+    /// `n` and its dominators, from `n` up to the root.
+    pub fn dominators(&self, n: usize) -> Vec<usize> {
+        let mut result = Vec::new();
+        if self.is_reachable(n) {
+            let mut d = Some(n);
+            while let Some(x) = d {
+                result.push(x);
+                d = self.idom[x];
+            }
+        }
+        result
+    }
+}
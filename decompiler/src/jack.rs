@@ -0,0 +1,316 @@
+//! Jack source for decompiled classes. A method called on an object is
+//! written `obj.method(args)` when `obj` is declared with the method's class,
+//! and `Class.method(obj, args)` otherwise, which compiles to the same VM
+//! code. Memory Jack has no name for, such as the `that` segment outside an
+//! array access, is read and written with `Memory.peek` and `Memory.poke`.
+
+use std::collections::HashMap;
+
+use parser::Span;
+use types::{generated_name, DeclaredClass, DeclaredSubroutine, Type};
+use untyped_ir::UnTypedIR;
+
+/// How a VM variable is spelled in Jack.
+enum Name {
+    /// A Jack variable or `this`.
+    Var(String),
+    /// A word of memory, given as the Jack expression of its address.
+    Memory(String),
+}
+
+/// Declarations of `vars` with `kind`, such as `var` or `field`. Runs of
+/// variables of the same type share one declaration, so the order, which
+/// decides each variable's index in its segment, is kept.
+fn declarations(kind: &str, vars: &[(String, Type)]) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    let mut names: Vec<&str> = Vec::new();
+    for (i, (name, ty)) in vars.iter().enumerate() {
+        names.push(name);
+        if vars.get(i + 1).is_none_or(|(_, next)| next != ty) {
+            result.push(format!("{} {} {};", kind, ty, names.join(", ")));
+            names.clear();
+        }
+    }
+    result
+}
+
+/// Prints `class` as a Jack class. With `spans`, every statement is
+/// preceded by a comment giving the VM lines it came from.
+pub fn class_to_jack(class: &DeclaredClass, spans: bool) -> String {
+    let no_names = HashMap::new();
+    let mut emitter = Emitter { class, vars: &[], names: &no_names, spans, out: String::new(), depth: 0 };
+    emitter.line(&format!("class {} {{", class.name));
+    emitter.depth += 1;
+    let named = |prefix: &str, types: &[Type]| -> Vec<(String, Type)> {
+        types.iter().enumerate().map(|(i, ty)| (emitter.var(&format!("{}{}", prefix, i)), ty.clone())).collect()
+    };
+    let mut fields = declarations("static", &named(&format!("{}.STATIC_", class.name), &class.statics));
+    fields.extend(declarations("field", &named("field_", &class.fields)));
+    for decl in fields.iter() {
+        emitter.line(decl);
+    }
+    for (i, s) in class.subroutines.iter().enumerate() {
+        if i > 0 || !fields.is_empty() {
+            emitter.out.push('\n');
+        }
+        emitter.subroutine(s);
+    }
+    emitter.depth -= 1;
+    emitter.line("}");
+    emitter.out
+}
+
+struct Emitter<'a> {
+    class: &'a DeclaredClass,
+    /// The variables of the subroutine being printed, and the names
+    /// annotated for them.
+    vars: &'a [(String, Type)],
+    names: &'a HashMap<String, String>,
+    spans: bool,
+    out: String,
+    depth: usize,
+}
+
+impl<'a> Emitter<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn subroutine(&mut self, sub: &'a DeclaredSubroutine) {
+        let sig = &sub.sig;
+        self.vars = &sub.vars;
+        self.names = &sub.names;
+        let name = sig.name.rsplit('.').next().unwrap_or(&sig.name);
+        let ret = sub.ret.as_ref().map_or("void".to_string(), Type::to_string);
+        let declared = sig.declared_params();
+        let params: Vec<String> = sub.vars.iter()
+            .filter(|(v, _)| declared.contains(v))
+            .map(|(v, ty)| format!("{} {}", ty, self.var(v)))
+            .collect();
+        self.line(&format!("{} {} {}({}) {{", sig.kind, ret, name, params.join(", ")));
+        self.depth += 1;
+        let locals: Vec<(String, Type)> = sub.vars.iter()
+            .filter(|(v, _)| !declared.contains(v))
+            .map(|(v, ty)| (self.var(v), ty.clone()))
+            .collect();
+        for decl in declarations("var", &locals) {
+            self.line(&decl);
+        }
+        self.statements(&sub.body);
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn statements(&mut self, stmts: &[UnTypedIR]) {
+        for stmt in stmts.iter() {
+            self.statement(stmt, None);
+        }
+    }
+
+    /// Prints `stmt`, which came from the VM lines `span` if known.
+    fn statement(&mut self, stmt: &UnTypedIR, span: Option<&Span>) {
+        if let UnTypedIR::Spanned(span, s) = stmt {
+            return self.statement(s, Some(span));
+        }
+        if let (true, Some(span)) = (self.spans, span) {
+            self.line(&format!("// {}", span));
+        }
+        match stmt {
+            UnTypedIR::Assign(target, value) => {
+                let value = match (target.unspanned(), value.unspanned()) {
+                    (UnTypedIR::Var(v), UnTypedIR::ConstInt(0)) if self.type_of(v) == Some(Type::Boolean) => "false".into(),
+                    _ => self.expr(value),
+                };
+                let text = match target.unspanned() {
+                    UnTypedIR::Var(v) => match self.name(v) {
+                        Name::Var(v) => format!("let {} = {};", v, value),
+                        Name::Memory(address) => format!("do Memory.poke({}, {});", address, value),
+                    },
+                    UnTypedIR::ArrayOffset(base, offset) => match self.array(base) {
+                        Some(base) => format!("let {}[{}] = {};", base, self.expr(offset), value),
+                        None => format!("do Memory.poke({}, {});", self.address(base, offset), value),
+                    },
+                    address => format!("do Memory.poke({}, {});", self.expr(address), value),
+                };
+                self.line(&text);
+            }
+            UnTypedIR::Return(e) => {
+                let text = format!("return {};", self.expr(e));
+                self.line(&text);
+            }
+            UnTypedIR::If(cond, taken, not_taken, cont) => {
+                let text = format!("if ({}) {{", self.expr(cond));
+                self.line(&text);
+                self.block(taken);
+                if !not_taken.is_empty() {
+                    self.line("} else {");
+                    self.block(not_taken);
+                }
+                self.line("}");
+                self.statements(cont);
+            }
+            UnTypedIR::While(cond, body, cont) => {
+                let text = format!("while ({}) {{", self.expr(cond));
+                self.line(&text);
+                self.block(body);
+                self.line("}");
+                self.statements(cont);
+            }
+            UnTypedIR::ReturnVoid => self.line("return;"),
+            UnTypedIR::Do(call) => {
+                let text = format!("do {};", self.expr(call));
+                self.line(&text);
+            }
+            UnTypedIR::Call(..) | UnTypedIR::MethodCall(..) => {
+                let text = format!("do {};", self.expr(stmt));
+                self.line(&text);
+            }
+            UnTypedIR::FuncDef(..) => {}
+            _ => unreachable!("expression used as a statement"),
+        }
+    }
+
+    fn block(&mut self, stmts: &[UnTypedIR]) {
+        self.depth += 1;
+        self.statements(stmts);
+        self.depth -= 1;
+    }
+
+    /// The Jack name of the variable `v` as the IR spells it.
+    fn var(&self, v: &str) -> String {
+        match self.name(v) {
+            Name::Var(v) => v,
+            Name::Memory(address) => format!("Memory.peek({})", address),
+        }
+    }
+
+    fn name(&self, v: &str) -> Name {
+        if let Some(name) = self.names.get(v).or_else(|| self.class.names.get(v)) {
+            return Name::Var(name.clone());
+        }
+        let index = |prefix: &str| v.strip_prefix(prefix).and_then(|i| i.parse::<usize>().ok());
+        if let Some(i) = index("POINTER_") {
+            Name::Memory((3 + i).to_string())
+        } else if let Some(i) = index("THIS_") {
+            Name::Memory(offset_address("Memory.peek(3)", i))
+        } else if let Some(i) = index("THAT_") {
+            Name::Memory(offset_address("Memory.peek(4)", i))
+        } else {
+            Name::Var(generated_name(&self.class.name, v))
+        }
+    }
+
+    /// `base` as the array variable of a Jack `base[i]`, if it is one.
+    fn array(&self, base: &UnTypedIR) -> Option<String> {
+        match base.unspanned() {
+            UnTypedIR::Var(v) if v != "this" => match self.name(v) {
+                Name::Var(v) => Some(v),
+                Name::Memory(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The Jack expression of the address `offset` words past `base`.
+    fn address(&self, base: &UnTypedIR, offset: &UnTypedIR) -> String {
+        match offset.unspanned() {
+            UnTypedIR::ConstInt(0) => self.expr(base),
+            _ => self.expr(&UnTypedIR::Binary("+".into(), Box::new(base.clone()), Box::new(offset.clone()))),
+        }
+    }
+
+    fn expr(&self, e: &UnTypedIR) -> String {
+        match e {
+            UnTypedIR::Spanned(_, e) => self.expr(e),
+            UnTypedIR::ConstInt(i) if *i >= 0 => i.to_string(),
+            UnTypedIR::ConstInt(i) if *i > -32768 => format!("-{}", -i),
+            UnTypedIR::ConstInt(_) => "(-32767 - 1)".into(),
+            UnTypedIR::ConstString(s) => format!("\"{}\"", s),
+            UnTypedIR::Var(v) => self.var(v),
+            UnTypedIR::Unary(op, e) if op == "~" && *e.unspanned() == UnTypedIR::ConstInt(0) => "true".into(),
+            UnTypedIR::Unary(op, e) => format!("{}{}", op, self.term(e)),
+            UnTypedIR::Binary(op, e1, e2) => {
+                // Jack has no precedence and evaluates operators left to
+                // right, so a right operand always needs parentheses. A left
+                // one only needs them to read the same way to someone used
+                // to `*` binding tighter than `+`.
+                let left = match e1.unspanned() {
+                    UnTypedIR::Binary(op1, ..) if precedence(op1) >= precedence(op) => self.expr(e1),
+                    _ => self.term(e1),
+                };
+                format!("{} {} {}", left, op, self.term(e2))
+            }
+            UnTypedIR::Call(func, args) => format!("{}({})", func, self.args(args)),
+            UnTypedIR::MethodCall(obj, func, args) => {
+                let (class, method) = func.split_at(func.rfind('.').unwrap_or(0));
+                match obj.unspanned() {
+                    UnTypedIR::Var(v) if v == "this" && class == self.class.name => {
+                        format!("{}({})", &method[1..], self.args(args))
+                    }
+                    UnTypedIR::Var(v) if self.type_of(v) == Some(Type::from_name(class)) => {
+                        format!("{}{}({})", self.var(v), method, self.args(args))
+                    }
+                    obj => {
+                        let mut all = vec![obj.clone()];
+                        all.extend(args.iter().cloned());
+                        format!("{}({})", func, self.args(&all))
+                    }
+                }
+            }
+            UnTypedIR::ArrayOffset(base, offset) => match self.array(base) {
+                Some(base) => format!("{}[{}]", base, self.expr(offset)),
+                None => format!("Memory.peek({})", self.address(base, offset)),
+            },
+            UnTypedIR::FuncDef(..) | UnTypedIR::Assign(..) | UnTypedIR::Return(..) |
+            UnTypedIR::ReturnVoid | UnTypedIR::Do(..) |
+            UnTypedIR::If(..) | UnTypedIR::While(..) => unreachable!("statement used as an expression"),
+        }
+    }
+
+    /// `e` as a Jack term: an operand that binds tighter than any operator.
+    fn term(&self, e: &UnTypedIR) -> String {
+        match e.unspanned() {
+            UnTypedIR::Binary(..) => format!("({})", self.expr(e)),
+            UnTypedIR::ConstInt(i) if *i < 0 => format!("({})", self.expr(e)),
+            e => self.expr(e),
+        }
+    }
+
+    /// The declared type of the variable `v`, if it has one.
+    fn type_of(&self, v: &str) -> Option<Type> {
+        let static_prefix = format!("{}.STATIC_", self.class.name);
+        if let Some(i) = v.strip_prefix(&static_prefix).and_then(|i| i.parse::<usize>().ok()) {
+            self.class.statics.get(i).cloned()
+        } else if let Some(i) = v.strip_prefix("field_").and_then(|i| i.parse::<usize>().ok()) {
+            self.class.fields.get(i).cloned()
+        } else {
+            self.vars.iter().find(|(name, _)| name == v).map(|(_, ty)| ty.clone())
+        }
+    }
+
+    fn args(&self, args: &[UnTypedIR]) -> String {
+        args.iter().map(|a| self.expr(a)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// How tightly `op` binds in the languages Jack borrows its operators from.
+fn precedence(op: &str) -> u8 {
+    match op {
+        "*" | "/" => 4,
+        "+" | "-" => 3,
+        "<" | ">" | "=" => 2,
+        "&" => 1,
+        _ => 0,
+    }
+}
+
+fn offset_address(base: &str, offset: usize) -> String {
+    match offset {
+        0 => base.to_string(),
+        i => format!("{} + {}", base, i),
+    }
+}
@@ -0,0 +1,184 @@
+//! JSON rendering of untyped IR, for tools that post-process decompiler
+//! output. Every node is an object whose `node` member names the variant.
+//! Spanned statements carry an extra `span` member instead of a wrapper.
+
+use parser::Span;
+use untyped_ir::{UnTypedClass, UnTypedIR};
+
+fn string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn list(out: &mut String, irs: &[UnTypedIR]) {
+    out.push('[');
+    for (i, ir) in irs.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        node(out, ir, None);
+    }
+    out.push(']');
+}
+
+fn field(out: &mut String, name: &str) {
+    out.push_str(",\"");
+    out.push_str(name);
+    out.push_str("\":");
+}
+
+fn node(out: &mut String, ir: &UnTypedIR, span: Option<&Span>) {
+    if let UnTypedIR::Spanned(span, e) = ir {
+        return node(out, e, Some(span));
+    }
+    out.push_str("{\"node\":");
+    match ir {
+        UnTypedIR::FuncDef(sig, body) => {
+            string(out, "FuncDef");
+            field(out, "kind");
+            string(out, &sig.kind.to_string());
+            field(out, "name");
+            string(out, &sig.name);
+            field(out, "params");
+            out.push_str(&sig.params.to_string());
+            field(out, "locals");
+            out.push_str(&sig.locals.to_string());
+            field(out, "void");
+            out.push_str(if sig.void { "true" } else { "false" });
+            field(out, "body");
+            list(out, body);
+        }
+        UnTypedIR::ConstInt(i) => {
+            string(out, "ConstInt");
+            field(out, "value");
+            out.push_str(&i.to_string());
+        }
+        UnTypedIR::ConstString(s) => {
+            string(out, "ConstString");
+            field(out, "value");
+            string(out, s);
+        }
+        UnTypedIR::Var(v) => {
+            string(out, "Var");
+            field(out, "name");
+            string(out, v);
+        }
+        UnTypedIR::Unary(op, e) => {
+            string(out, "Unary");
+            field(out, "op");
+            string(out, op);
+            field(out, "operand");
+            node(out, e, None);
+        }
+        UnTypedIR::Binary(op, e1, e2) => {
+            string(out, "Binary");
+            field(out, "op");
+            string(out, op);
+            field(out, "left");
+            node(out, e1, None);
+            field(out, "right");
+            node(out, e2, None);
+        }
+        UnTypedIR::Call(func, args) => {
+            string(out, "Call");
+            field(out, "function");
+            string(out, func);
+            field(out, "args");
+            list(out, args);
+        }
+        UnTypedIR::MethodCall(obj, func, args) => {
+            string(out, "MethodCall");
+            field(out, "object");
+            node(out, obj, None);
+            field(out, "function");
+            string(out, func);
+            field(out, "args");
+            list(out, args);
+        }
+        UnTypedIR::Assign(target, value) => {
+            string(out, "Assign");
+            field(out, "target");
+            node(out, target, None);
+            field(out, "value");
+            node(out, value, None);
+        }
+        UnTypedIR::Return(e) => {
+            string(out, "Return");
+            field(out, "value");
+            node(out, e, None);
+        }
+        UnTypedIR::ReturnVoid => {
+            string(out, "Return");
+            field(out, "value");
+            out.push_str("null");
+        }
+        UnTypedIR::Do(e) => {
+            string(out, "Do");
+            field(out, "call");
+            node(out, e, None);
+        }
+        UnTypedIR::If(cond, taken, not_taken, cont) => {
+            string(out, "If");
+            field(out, "cond");
+            node(out, cond, None);
+            field(out, "then");
+            list(out, taken);
+            field(out, "else");
+            list(out, not_taken);
+            field(out, "next");
+            list(out, cont);
+        }
+        UnTypedIR::While(cond, body, cont) => {
+            string(out, "While");
+            field(out, "cond");
+            node(out, cond, None);
+            field(out, "body");
+            list(out, body);
+            field(out, "next");
+            list(out, cont);
+        }
+        UnTypedIR::ArrayOffset(base, offset) => {
+            string(out, "ArrayOffset");
+            field(out, "base");
+            node(out, base, None);
+            field(out, "offset");
+            node(out, offset, None);
+        }
+        UnTypedIR::Spanned(..) => unreachable!(),
+    }
+    if let Some(span) = span {
+        field(out, "span");
+        out.push_str("{\"file\":");
+        string(out, &span.file);
+        out.push_str(&format!(",\"first_line\":{},\"last_line\":{}}}", span.first_line, span.last_line));
+    }
+    out.push('}');
+}
+
+pub fn ir_to_json(ir: &UnTypedIR) -> String {
+    let mut out = String::new();
+    node(&mut out, ir, None);
+    out
+}
+
+pub fn class_to_json(class: &UnTypedClass) -> String {
+    let mut out = String::from("{\"class\":");
+    string(&mut out, &class.name);
+    field(&mut out, "fields");
+    out.push_str(&class.fields.to_string());
+    field(&mut out, "subroutines");
+    list(&mut out, &class.subroutines);
+    out.push('}');
+    out
+}
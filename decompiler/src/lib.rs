@@ -1,3 +1,15 @@
 pub mod parser;
 pub mod untyped_ir;
-pub mod decompiler;
\ No newline at end of file
+pub mod types;
+pub mod annotations;
+pub mod dominators;
+pub mod loops;
+pub mod decompiler;
+pub mod program;
+pub mod json;
+pub mod jack;
+pub mod writer;
+pub mod diagnostic;
+pub mod stack;
+pub mod params;
+pub mod os_api;
\ No newline at end of file
@@ -0,0 +1,135 @@
+use dominators::DominatorTree;
+
+/// A natural loop: the header and every node that can reach one of the
+/// header's back edges without passing through the header.
+pub struct Loop {
+    pub header: usize,
+    /// The nodes of the loop in ascending order, header and nested loops
+    /// included.
+    pub body: Vec<usize>,
+    /// The nodes with a back edge to the header.
+    pub latches: Vec<usize>,
+    /// The edges leaving the loop, as (from, to) pairs.
+    pub exits: Vec<(usize, usize)>,
+    /// The innermost loop containing this one, as an index into
+    /// `LoopForest::loops`.
+    pub parent: Option<usize>,
+}
+
+impl Loop {
+    pub fn contains(&self, n: usize) -> bool {
+        self.body.binary_search(&n).is_ok()
+    }
+}
+
+/// The edges from a node to one of its ancestors in a depth-first walk
+/// from `root`.
+fn retreating_edges(succs: &[Vec<usize>], root: usize) -> Vec<(usize, usize)> {
+    let mut visited = vec![false; succs.len()];
+    let mut on_stack = vec![false; succs.len()];
+    let mut result = Vec::new();
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+    on_stack[root] = true;
+    while let Some(&mut (n, ref mut next)) = stack.last_mut() {
+        if let Some(&s) = succs[n].get(*next) {
+            *next += 1;
+            if on_stack[s] {
+                result.push((n, s));
+            } else if !visited[s] {
+                visited[s] = true;
+                on_stack[s] = true;
+                stack.push((s, 0));
+            }
+        } else {
+            on_stack[n] = false;
+            stack.pop();
+        }
+    }
+    result
+}
+
+/// The natural loops of a graph and how they nest. Loops sharing a header
+/// are merged into one; retreating edges to a node that does not dominate
+/// their source belong to no loop and make the graph irreducible.
+pub struct LoopForest {
+    loops: Vec<Loop>,
+    irreducible: Vec<(usize, usize)>,
+    header_of: Vec<Option<usize>>,
+    innermost: Vec<Option<usize>>,
+}
+
+impl LoopForest {
+    pub fn new(succs: &[Vec<usize>], preds: &[Vec<usize>], doms: &DominatorTree) -> Self {
+        let n = succs.len();
+        let mut loops = Vec::new();
+        for h in 0..n {
+            let latches: Vec<usize> = preds[h].iter().cloned().filter(|&p| doms.dominates(h, p)).collect();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut in_body = vec![false; n];
+            in_body[h] = true;
+            let mut stack = latches.clone();
+            while let Some(b) = stack.pop() {
+                if !in_body[b] {
+                    in_body[b] = true;
+                    stack.extend(preds[b].iter().filter(|&&p| doms.is_reachable(p)));
+                }
+            }
+            let body: Vec<usize> = (0..n).filter(|&b| in_body[b]).collect();
+            let exits = body.iter()
+                .flat_map(|&b| succs[b].iter().filter(|&&s| !in_body[s]).map(move |&s| (b, s)))
+                .collect();
+            loops.push(Loop { header: h, body, latches, exits, parent: None });
+        }
+
+        // A loop's parent is the smallest other loop containing its header.
+        let mut innermost: Vec<Option<usize>> = vec![None; n];
+        let mut by_size: Vec<usize> = (0..loops.len()).collect();
+        by_size.sort_by_key(|&l| loops[l].body.len());
+        for (i, &l) in by_size.iter().enumerate() {
+            loops[l].parent = by_size[i + 1..].iter().cloned()
+                .find(|&p| loops[p].contains(loops[l].header));
+            for &b in loops[l].body.iter() {
+                if innermost[b].is_none() {
+                    innermost[b] = Some(l);
+                }
+            }
+        }
+        let mut header_of = vec![None; n];
+        for (l, lp) in loops.iter().enumerate() {
+            header_of[lp.header] = Some(l);
+        }
+        let irreducible = retreating_edges(succs, doms.root())
+            .into_iter()
+            .filter(|&(from, to)| !doms.dominates(to, from))
+            .collect();
+        LoopForest { loops, irreducible, header_of, innermost }
+    }
+
+    /// The retreating edges, as (from, to) pairs, that enter a cycle
+    /// somewhere other than a node dominating the whole cycle. Empty if the
+    /// graph is reducible.
+    pub fn irreducible_edges(&self) -> &[(usize, usize)] {
+        &self.irreducible
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    /// The loop headed by `n`, if `n` is a loop header.
+    pub fn loop_at(&self, n: usize) -> Option<&Loop> {
+        self.header_of[n].map(|l| &self.loops[l])
+    }
+
+    pub fn is_header(&self, n: usize) -> bool {
+        self.header_of[n].is_some()
+    }
+
+    /// The index of the innermost loop containing `n`.
+    pub fn innermost(&self, n: usize) -> Option<usize> {
+        self.innermost[n]
+    }
+}
@@ -1,17 +1,346 @@
 extern crate decompiler;
 
-use decompiler::parser::{vm_commands};
-use decompiler::decompiler::to_untyped_ir;
+use decompiler::annotations::Annotations;
+use decompiler::decompiler::{decompile, function_graph, CfgError, Options as DecompileOptions};
+use decompiler::diagnostic::{Diagnostic, Severity};
+use decompiler::jack::class_to_jack;
+use decompiler::json::class_to_json;
+use decompiler::program::{glob_match, Function, Program};
+use decompiler::params;
+use decompiler::stack;
+use decompiler::types;
+use decompiler::untyped_ir::{UnTypedClass, UnTypedIR};
 
+use std::env;
 use std::fs;
-use std::io::{BufWriter};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "\
+usage: decompiler <command> [options] <path>...
+
+Paths are .vm files or directories of .vm files, one file per class.
+
+commands:
+  decompile   decompile the program into --format (default: jack)
+  ir          print the untyped IR
+  cfg         print the control-flow graph of every function as DOT
+  check       parse the program and check that every function keeps the
+              operand stack balanced, and that calls agree on how many
+              arguments each function takes
+
+options:
+  -o, --output <dir>      write one file per class (per function for cfg)
+                          into <dir> instead of printing to stdout
+  -f, --format <format>   jack, ir, json or dot
+      --function <glob>   only handle functions matching <glob>, where `*`
+                          matches any text, e.g. `Main.*`
+      --spans             annotate statements with their VM source lines
+      --math-calls        keep calls of Math.multiply and Math.divide
+                          instead of turning them into `*` and `/`
+      --annotations <file>
+                          read the types and names of variables from
+                          <file> when decompiling into Jack
+  -h, --help              print this message
+
+exit status: 0 on success, 1 if the input has errors, 2 on bad usage,
+3 if the output cannot be written.";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Decompile,
+    Ir,
+    Cfg,
+    Check,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Jack,
+    Ir,
+    Json,
+    Dot,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Jack => "jack",
+            Format::Ir => "ir",
+            Format::Json => "json",
+            Format::Dot => "dot",
+        }
+    }
+}
+
+struct Options {
+    command: Command,
+    paths: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    format: Format,
+    function: Option<String>,
+    spans: bool,
+    math_calls: bool,
+    annotations: Option<PathBuf>,
+}
+
+enum Failure {
+    Usage(String),
+    Input(String),
+    Output(io::Error),
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Input(_) => 1,
+            Failure::Usage(_) => 2,
+            Failure::Output(_) => 3,
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Output(e)
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, Failure> {
+    let command = match args.next().as_deref() {
+        Some("decompile") => Command::Decompile,
+        Some("ir") => Command::Ir,
+        Some("cfg") => Command::Cfg,
+        Some("check") => Command::Check,
+        Some("-h") | Some("--help") => return Err(Failure::Usage(String::new())),
+        Some(other) => return Err(Failure::Usage(format!("unknown command `{}`", other))),
+        None => return Err(Failure::Usage("missing command".into())),
+    };
+    let mut options = Options {
+        command,
+        paths: Vec::new(),
+        output: None,
+        format: match command {
+            Command::Cfg => Format::Dot,
+            Command::Decompile => Format::Jack,
+            _ => Format::Ir,
+        },
+        function: None,
+        spans: false,
+        math_calls: false,
+        annotations: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next().ok_or_else(|| Failure::Usage(format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => options.output = Some(value(&arg)?.into()),
+            "-f" | "--format" => {
+                if command != Command::Decompile {
+                    return Err(Failure::Usage(format!("{} only applies to decompile", arg)));
+                }
+                options.format = match value(&arg)?.as_str() {
+                    "jack" => Format::Jack,
+                    "ir" => Format::Ir,
+                    "json" => Format::Json,
+                    "dot" => Format::Dot,
+                    other => return Err(Failure::Usage(format!("unknown format `{}`", other))),
+                };
+            }
+            "--function" => options.function = Some(value(&arg)?),
+            "--spans" => options.spans = true,
+            "--math-calls" => options.math_calls = true,
+            "--annotations" => {
+                if command != Command::Decompile {
+                    return Err(Failure::Usage(format!("{} only applies to decompile", arg)));
+                }
+                options.annotations = Some(value(&arg)?.into());
+            }
+            "-h" | "--help" => return Err(Failure::Usage(String::new())),
+            flag if flag.starts_with('-') => {
+                return Err(Failure::Usage(format!("unknown option `{}`", flag)));
+            }
+            path => options.paths.push(path.into()),
+        }
+    }
+    if options.paths.is_empty() {
+        return Err(Failure::Usage("no input paths given".into()));
+    }
+    Ok(options)
+}
+
+/// Whether `--function` selects the function or subroutine `name`.
+fn selected(options: &Options, name: &str) -> bool {
+    options.function.as_ref().is_none_or(|pattern| glob_match(pattern, name))
+}
+
+/// Where output goes: stdout, or one file per item in a directory.
+struct Sink {
+    dir: Option<PathBuf>,
+}
+
+impl Sink {
+    fn emit(&self, name: &str, format: Format, contents: &str) -> io::Result<()> {
+        match self.dir {
+            Some(ref dir) => fs::write(dir.join(format!("{}.{}", name, format.extension())), contents),
+            None => io::stdout().write_all(contents.as_bytes()),
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), Failure> {
+    // `--function` only narrows what is printed: inference needs every
+    // caller and callee to see how a function is used.
+    let program = Program::load(&options.paths).map_err(|e| Failure::Input(e.to_string()))?;
+    if options.command == Command::Check {
+        let mut diags = Vec::new();
+        for function in program.classes.iter().flat_map(|c| c.functions.iter()) {
+            if !selected(options, &function.name) {
+                continue;
+            }
+            match function_graph(function) {
+                Ok(graph) => {
+                    stack::verify(&graph, &mut diags);
+                }
+                Err(e) => diags.push(graph_error(function, e)),
+            }
+        }
+        params::infer(&program, &mut diags);
+        for d in diags.iter() {
+            eprintln!("{}", d);
+        }
+        return fail_on_errors(&diags);
+    }
+    if let Some(ref dir) = options.output {
+        fs::create_dir_all(dir)?;
+    }
+    let sink = Sink { dir: options.output.clone() };
+    let format = match options.command {
+        Command::Ir => Format::Ir,
+        _ => options.format,
+    };
+    if format == Format::Dot {
+        let mut diags = Vec::new();
+        for class in program.classes.iter() {
+            for function in class.functions.iter().filter(|f| selected(options, &f.name)) {
+                let graph = match function_graph(function) {
+                    Ok(graph) => graph,
+                    Err(e) => {
+                        diags.push(graph_error(function, e));
+                        continue;
+                    }
+                };
+                let mut dot = Vec::new();
+                graph.write_graphviz(&function.name, &mut dot)?;
+                sink.emit(&function.name, format, &String::from_utf8_lossy(&dot))?;
+            }
+        }
+        for d in diags.iter() {
+            eprintln!("{}", d);
+        }
+        return fail_on_errors(&diags);
+    }
+    let mut diags = Vec::new();
+    let classes = decompile(&program, DecompileOptions { math_calls: options.math_calls }, &mut diags);
+    for d in diags.iter() {
+        eprintln!("{}", d);
+    }
+    emit(options, &sink, format, classes)?;
+    // Whatever could be decompiled is written out before failing.
+    fail_on_errors(&diags)
+}
+
+/// Fails with the number of errors among `diags`, if there are any.
+fn fail_on_errors(diags: &[Diagnostic]) -> Result<(), Failure> {
+    let errors = diags.iter().filter(|d| d.severity == Severity::Error).count();
+    if errors > 0 {
+        let noun = if errors == 1 { "error" } else { "errors" };
+        return Err(Failure::Input(format!("{} {}", errors, noun)));
+    }
+    Ok(())
+}
+
+/// Reports why `function` has no control-flow graph.
+fn graph_error(function: &Function, e: CfgError) -> Diagnostic {
+    match e {
+        CfgError::UndefinedLabel(label, span) => Diagnostic::error(Some(span), format!(
+            "{} jumps to undefined label `{}`", function.name, label,
+        )),
+        CfgError::DuplicateLabel(label, span) => Diagnostic::error(Some(span), format!(
+            "{} defines label `{}` twice", function.name, label,
+        )),
+        e => Diagnostic::error(Some(function.span.clone()), format!("{}: {}", function.name, e)),
+    }
+}
+
+fn emit(options: &Options, sink: &Sink, format: Format, mut classes: Vec<UnTypedClass>) -> Result<(), Failure> {
+    if format != Format::Jack {
+        for class in classes.iter_mut() {
+            class.subroutines.retain(|s| match s {
+                UnTypedIR::FuncDef(sig, _) => selected(options, &sig.name),
+                _ => true,
+            });
+        }
+        classes.retain(|c| !c.subroutines.is_empty());
+    }
+    if format == Format::Json && options.output.is_none() {
+        // Classes printed to stdout form a single JSON array.
+        let json: Vec<String> = classes.iter().map(class_to_json).collect();
+        return Ok(sink.emit("", format, &format!("[{}]\n", json.join(",\n")))?);
+    }
+    if format == Format::Jack {
+        let annotations = match options.annotations {
+            Some(ref path) => {
+                let source = fs::read_to_string(path)
+                    .map_err(|e| Failure::Input(format!("{}: {}", path.display(), e)))?;
+                Annotations::parse(&path.to_string_lossy(), &source).map_err(|errors| {
+                    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                    Failure::Input(errors.join("\n"))
+                })?
+            }
+            None => Annotations::default(),
+        };
+        let mut diags = Vec::new();
+        let mut classes = types::infer(classes, &annotations, &mut diags);
+        for d in diags.iter() {
+            eprintln!("{}", d);
+        }
+        for class in classes.iter_mut() {
+            class.subroutines.retain(|s| selected(options, &s.sig.name));
+        }
+        classes.retain(|c| !c.subroutines.is_empty());
+        for class in classes.iter() {
+            sink.emit(&class.name, format, &class_to_jack(class, options.spans))?;
+        }
+        return Ok(());
+    }
+    for class in classes.iter() {
+        let text = match format {
+            Format::Ir if options.spans => format!("{:#}\n", class),
+            Format::Ir => format!("{}\n", class),
+            Format::Json => format!("{}\n", class_to_json(class)),
+            Format::Jack | Format::Dot => unreachable!(),
+        };
+        sink.emit(&class.name, format, &text)?;
+    }
+    Ok(())
+}
 
 fn main() {
-    let input = fs::File::open("test.vm").unwrap();
-    let output = fs::File::create("test.dot").unwrap();
-    let mut writer = BufWriter::new(output);
-    let untyped_irs = to_untyped_ir(&mut vm_commands(input).into_iter());
-    for c in untyped_irs {
-        println!("{}", c.reconstruct_const_string());
+    let result = parse_args(env::args().skip(1)).and_then(|options| run(&options));
+    if let Err(failure) = result {
+        match failure {
+            Failure::Usage(ref message) if message.is_empty() => println!("{}", USAGE),
+            Failure::Usage(ref message) => eprintln!("decompiler: {}\n\n{}", message, USAGE),
+            Failure::Input(ref message) => eprintln!("{}", message),
+            Failure::Output(ref e) => eprintln!("decompiler: cannot write output: {}", e),
+        }
+        process::exit(match failure {
+            Failure::Usage(ref message) if message.is_empty() => 0,
+            ref f => f.exit_code(),
+        });
     }
 }
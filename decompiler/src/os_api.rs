@@ -0,0 +1,110 @@
+//! The subroutines of the eight Jack OS classes, as the book specifies
+//! them. Programs call them without defining them, so this table is all
+//! there is to know their kinds, parameters and results.
+
+use types::Type;
+use types::Type::*;
+use untyped_ir::SubroutineKind;
+use untyped_ir::SubroutineKind::*;
+
+pub struct OsSubroutine {
+    pub kind: SubroutineKind,
+    /// The full name, such as `String.appendChar`.
+    pub name: &'static str,
+    /// The declared parameters, leaving out a method's object.
+    pub params: &'static [Type],
+    /// `None` for `void`.
+    pub ret: Option<Type>,
+}
+
+impl OsSubroutine {
+    pub fn class(&self) -> &'static str {
+        self.name.split('.').next().unwrap()
+    }
+
+    /// The parameters as the VM passes them, a method's object first.
+    pub fn vm_params(&self) -> Vec<Type> {
+        let this = match self.kind {
+            Method => Some(Type::from_name(self.class())),
+            _ => None,
+        };
+        this.into_iter().chain(self.params.iter().cloned()).collect()
+    }
+
+    /// The argument count of every `call` of the subroutine.
+    pub fn arity(&self) -> usize {
+        self.params.len() + if self.kind == Method { 1 } else { 0 }
+    }
+}
+
+macro_rules! os {
+    ($($kind:ident $ret:tt $name:literal($($param:ident),*);)*) => {
+        &[$(OsSubroutine { kind: $kind, name: $name, params: &[$($param),*], ret: os!(@ret $ret) }),*]
+    };
+    (@ret void) => { None };
+    (@ret $ty:ident) => { Some($ty) };
+}
+
+pub static OS_API: &[OsSubroutine] = os! {
+    Function void "Math.init"();
+    Function Int "Math.abs"(Int);
+    Function Int "Math.multiply"(Int, Int);
+    Function Int "Math.divide"(Int, Int);
+    Function Int "Math.min"(Int, Int);
+    Function Int "Math.max"(Int, Int);
+    Function Int "Math.sqrt"(Int);
+
+    Constructor String "String.new"(Int);
+    Method void "String.dispose"();
+    Method Int "String.length"();
+    Method Char "String.charAt"(Int);
+    Method void "String.setCharAt"(Int, Char);
+    Method String "String.appendChar"(Char);
+    Method void "String.eraseLastChar"();
+    Method Int "String.intValue"();
+    Method void "String.setInt"(Int);
+    Function Char "String.backSpace"();
+    Function Char "String.doubleQuote"();
+    Function Char "String.newLine"();
+
+    Function Array "Array.new"(Int);
+    Method void "Array.dispose"();
+
+    Function void "Output.init"();
+    Function void "Output.moveCursor"(Int, Int);
+    Function void "Output.printChar"(Char);
+    Function void "Output.printString"(String);
+    Function void "Output.printInt"(Int);
+    Function void "Output.println"();
+    Function void "Output.backSpace"();
+
+    Function void "Screen.init"();
+    Function void "Screen.clearScreen"();
+    Function void "Screen.setColor"(Boolean);
+    Function void "Screen.drawPixel"(Int, Int);
+    Function void "Screen.drawLine"(Int, Int, Int, Int);
+    Function void "Screen.drawRectangle"(Int, Int, Int, Int);
+    Function void "Screen.drawCircle"(Int, Int, Int);
+
+    Function void "Keyboard.init"();
+    Function Char "Keyboard.keyPressed"();
+    Function Char "Keyboard.readChar"();
+    Function String "Keyboard.readLine"(String);
+    Function Int "Keyboard.readInt"(String);
+
+    Function void "Memory.init"();
+    Function Int "Memory.peek"(Int);
+    Function void "Memory.poke"(Int, Int);
+    Function Array "Memory.alloc"(Int);
+    Function void "Memory.deAlloc"(Array);
+
+    Function void "Sys.init"();
+    Function void "Sys.halt"();
+    Function void "Sys.error"(Int);
+    Function void "Sys.wait"(Int);
+};
+
+/// The OS subroutine called `name`, if there is one.
+pub fn lookup(name: &str) -> Option<&'static OsSubroutine> {
+    OS_API.iter().find(|s| s.name == name)
+}
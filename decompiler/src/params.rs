@@ -0,0 +1,85 @@
+//! Parameter counts of subroutines. The VM only records how many locals a
+//! function has, so the number of parameters is taken from both ends: the
+//! highest `argument` a body touches and the argument count of every `call`
+//! in the program. Call sites that disagree with each other or with the
+//! body are reported as warnings, and the largest count wins. Calls of the
+//! OS are checked against its signatures instead.
+
+use std::collections::{HashMap, HashSet};
+
+use diagnostic::Diagnostic;
+use os_api;
+use parser::{Segment, Span, VmCommand};
+use program::Program;
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 { "argument" } else { "arguments" }
+}
+
+/// The number of parameters of every subroutine the program defines or
+/// calls, including the object a method is called on.
+pub fn infer(program: &Program, diags: &mut Vec<Diagnostic>) -> HashMap<String, usize> {
+    // The first call site of each subroutine and its argument count, and
+    // the largest count of any call site.
+    let mut calls: HashMap<&str, (usize, &Span, usize)> = HashMap::new();
+    let mut used: HashMap<&str, (usize, &Span)> = HashMap::new();
+    let defined: HashSet<&str> = program.classes.iter()
+        .flat_map(|c| c.functions.iter())
+        .map(|f| f.name.as_str())
+        .collect();
+    for function in program.classes.iter().flat_map(|c| c.functions.iter()) {
+        let mut highest = None;
+        for c in function.body.iter() {
+            match c.node {
+                VmCommand::Push(Segment::ARG, i) | VmCommand::Pop(Segment::ARG, i) if i >= 0 => {
+                    let i = i as usize;
+                    if highest.is_none_or(|(h, _)| i > h) {
+                        highest = Some((i, &c.span));
+                    }
+                }
+                VmCommand::Call(ref name, n) => {
+                    let n = n.max(0) as usize;
+                    let os = os_api::lookup(name).filter(|_| !defined.contains(name.as_str()));
+                    match (os, calls.get(name.as_str())) {
+                        (Some(os), _) if os.arity() != n => diags.push(Diagnostic::warning(Some(c.span.clone()), format!(
+                            "`{}` takes {} {} but is called with {} here",
+                            name, os.arity(), plural(os.arity()), n,
+                        ))),
+                        (_, None) => {
+                            calls.insert(name, (n, &c.span, n));
+                        }
+                        (None, Some(&(m, first, _))) if m != n => diags.push(Diagnostic::warning(Some(c.span.clone()), format!(
+                            "`{}` is called with {} {} here but with {} at {}",
+                            name, n, plural(n), m, first,
+                        ))),
+                        _ => {}
+                    }
+                    if let Some(call) = calls.get_mut(name.as_str()) {
+                        call.2 = call.2.max(n);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(h) = highest {
+            used.insert(&function.name, h);
+        }
+    }
+
+    let mut counts: HashMap<String, usize> = calls.iter().map(|(&name, &(_, _, n))| (name.to_string(), n)).collect();
+    for function in program.classes.iter().flat_map(|c| c.functions.iter()) {
+        let name = function.name.as_str();
+        let used = used.get(name).map(|&(i, span)| (i + 1, span));
+        if let (Some((u, span)), Some(&(_, _, n))) = (used, calls.get(name)) {
+            if u > n {
+                diags.push(Diagnostic::warning(Some(span.clone()), format!(
+                    "`{}` uses argument {} but is called with {} {}",
+                    name, u - 1, n, plural(n),
+                )));
+            }
+        }
+        let count = counts.entry(name.to_string()).or_insert(0);
+        *count = (*count).max(used.map_or(0, |(u, _)| u));
+    }
+    counts
+}
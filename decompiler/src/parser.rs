@@ -1,6 +1,8 @@
+use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
 use std::io::{Read, BufReader};
+use std::rc::Rc;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Segment {
@@ -15,32 +17,42 @@ pub enum Segment {
 }
 
 impl Segment {
-    fn get_string(&self) -> &'static str {
+    /// The segment's name in VM source, such as `local`.
+    pub fn as_str(&self) -> &'static str {
         match self {
-            &Segment::LCL => "LCL",
-            &Segment::ARG => "ARG",
-            &Segment::THIS => "THIS",
-            &Segment::THAT => "THAT",
-            _ => panic!("no representation for {:?}", self),
+            Segment::LCL => "local",
+            Segment::ARG => "argument",
+            Segment::THIS => "this",
+            Segment::THAT => "that",
+            Segment::CONST => "constant",
+            Segment::POINTER => "pointer",
+            Segment::STATIC => "static",
+            Segment::TEMP => "temp",
         }
     }
 
-    fn from_string(s: &str) -> Self {
+    fn from_string(s: &str) -> Option<Self> {
         match s {
-            "local" => Segment::LCL,
-            "argument" => Segment::ARG,
-            "this" => Segment::THIS,
-            "that" => Segment::THAT,
-            "constant" => Segment::CONST,
-            "pointer" => Segment::POINTER,
-            "static" => Segment::STATIC,
-            "temp" => Segment::TEMP,
-            _ => panic!("unknown command: {}", s),
+            "local" => Some(Segment::LCL),
+            "argument" => Some(Segment::ARG),
+            "this" => Some(Segment::THIS),
+            "that" => Some(Segment::THAT),
+            "constant" => Some(Segment::CONST),
+            "pointer" => Some(Segment::POINTER),
+            "static" => Some(Segment::STATIC),
+            "temp" => Some(Segment::TEMP),
+            _ => None,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum VmCommand {
     Push(Segment, i32),
     Pop(Segment, i32),
@@ -61,87 +73,387 @@ pub enum VmCommand {
     Return,
 }
 
+/// Prints the command as canonical VM source: lower-case keywords and
+/// operands separated by single spaces.
 impl Display for VmCommand {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &VmCommand::Push(seg, i) => write!(f, "push {:?} {}", seg, i),
-            &VmCommand::Pop(seg, i) => write!(f, "pop {:?} {}", seg, i),
-            &VmCommand::Label(ref s) => write!(f, "label {}", s),
-            &VmCommand::Goto(ref s) => write!(f, "goto {}", s),
-            &VmCommand::IfGoto(ref s) => write!(f, "if-goto {}", s),
-            &VmCommand::FunDef(ref s, i) => write!(f, "function {} {}", s, i),
-            &VmCommand::Call(ref s, i) => write!(f, "call {} {}", s, i),
-            cmd => write!(f, "{:?}", cmd),
+            VmCommand::Push(seg, i) => write!(f, "push {} {}", seg, i),
+            VmCommand::Pop(seg, i) => write!(f, "pop {} {}", seg, i),
+            VmCommand::Add => f.write_str("add"),
+            VmCommand::Sub => f.write_str("sub"),
+            VmCommand::Neg => f.write_str("neg"),
+            VmCommand::Eq => f.write_str("eq"),
+            VmCommand::Gt => f.write_str("gt"),
+            VmCommand::Lt => f.write_str("lt"),
+            VmCommand::And => f.write_str("and"),
+            VmCommand::Or => f.write_str("or"),
+            VmCommand::Not => f.write_str("not"),
+            VmCommand::Label(s) => write!(f, "label {}", s),
+            VmCommand::Goto(s) => write!(f, "goto {}", s),
+            VmCommand::IfGoto(s) => write!(f, "if-goto {}", s),
+            VmCommand::FunDef(s, i) => write!(f, "function {} {}", s, i),
+            VmCommand::Call(s, i) => write!(f, "call {} {}", s, i),
+            VmCommand::Return => f.write_str("return"),
         }
     }
 }
 
-impl VmCommand {
-    fn from_line(line: &str) -> Option<VmCommand> {
-        let part = line.split("//").nth(0).unwrap();
-        let trimmed = part.trim();
-        if trimmed.starts_with("push") {
-            let mut parts = trimmed.split(' ');
-            parts.next();
-            let segment = parts.next().unwrap();
-            let offset = parts.next().unwrap().parse::<i32>().unwrap();
-            Some(VmCommand::Push(Segment::from_string(segment), offset))
-        } else if trimmed.starts_with("pop") {
-            let mut parts = trimmed.split(' ');
-            parts.next();
-            let segment = parts.next().unwrap();
-            let offset = parts.next().unwrap().parse::<i32>().unwrap();
-            Some(VmCommand::Pop(Segment::from_string(segment), offset))
-        } else if trimmed.starts_with("add") {
-            Some(VmCommand::Add)
-        } else if trimmed.starts_with("sub") {
-            Some(VmCommand::Sub)
-        } else if trimmed.starts_with("neg") {
-            Some(VmCommand::Neg)
-        } else if trimmed.starts_with("eq") {
-            Some(VmCommand::Eq)
-        } else if trimmed.starts_with("gt") {
-            Some(VmCommand::Gt)
-        } else if trimmed.starts_with("lt") {
-            Some(VmCommand::Lt)
-        } else if trimmed.starts_with("and") {
-            Some(VmCommand::And)
-        } else if trimmed.starts_with("or") {
-            Some(VmCommand::Or)
-        } else if trimmed.starts_with("not") {
-            Some(VmCommand::Not)
-        } else if trimmed.starts_with("label") {
-            let label = trimmed.split(' ').nth(1).unwrap();
-            Some(VmCommand::Label(label.into()))
-        } else if trimmed.starts_with("goto") {
-            let label = trimmed.split(' ').nth(1).unwrap();
-            Some(VmCommand::Goto(label.into()))
-        } else if trimmed.starts_with("if-goto") {
-            let label = trimmed.split(' ').nth(1).unwrap();
-            Some(VmCommand::IfGoto(label.into()))
-        } else if trimmed.starts_with("call") {
-            let mut parts = trimmed.split(' ');
-            parts.next();
-            let fun = parts.next().unwrap();
-            let arg_count = parts.next().unwrap().parse::<i32>().unwrap();
-            Some(VmCommand::Call(fun.into(), arg_count))
-        } else if trimmed.starts_with("function") {
-            let mut parts = trimmed.split(' ');
-            parts.next();
-            let fun = parts.next().unwrap();
-            let var_count = parts.next().unwrap().parse::<i32>().unwrap();
-            Some(VmCommand::FunDef(fun.into(), var_count))
-        } else if trimmed.starts_with("return") {
-            Some(VmCommand::Return)
+/// The lines of a VM file that a command, or anything built from commands,
+/// came from. Lines are 1-based and inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+impl Span {
+    pub fn new(file: Rc<str>, line: usize) -> Self {
+        Span {
+            file,
+            first_line: line,
+            last_line: line,
+        }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn merge(&self, other: &Span) -> Span {
+        Span {
+            file: self.file.clone(),
+            first_line: self.first_line.min(other.first_line),
+            last_line: self.last_line.max(other.last_line),
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.first_line == self.last_line {
+            write!(f, "{}:{}", self.file, self.first_line)
         } else {
-            None
+            write!(f, "{}:{}-{}", self.file, self.first_line, self.last_line)
+        }
+    }
+}
+
+/// A value together with the source lines it was built from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T: Display> Display for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseErrorKind {
+    /// The first token of a line is not a VM command.
+    UnknownCommand,
+    /// `push` or `pop` names a segment that does not exist.
+    UnknownSegment,
+    /// The command ended before all of its operands were given.
+    MissingOperand,
+    /// An index or count operand is not a number.
+    InvalidNumber,
+    /// A segment index lies outside the segment, such as `temp 8` or a
+    /// constant that is negative or above 32767.
+    IndexOutOfRange,
+    /// `pop` into the `constant` segment, which cannot be written.
+    ReadOnlySegment,
+    /// A token follows a command that already has all of its operands.
+    UnexpectedToken,
+    /// A command comes before the first `function`, so no function holds
+    /// it.
+    OutsideFunction,
+    /// A file defines a function of another class than its first function,
+    /// such as `A.f` after `B.new`.
+    ForeignFunction,
+    /// A function is defined a second time, in the same file or another.
+    DuplicateFunction,
+    /// The source could not be read at all.
+    Io,
+}
+
+/// A problem found while parsing VM source, located by file, 1-based line
+/// and 1-based column of the offending token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            ParseErrorKind::UnknownCommand => "unknown command",
+            ParseErrorKind::UnknownSegment => "unknown segment",
+            ParseErrorKind::MissingOperand => "missing operand after",
+            ParseErrorKind::InvalidNumber => "invalid number",
+            ParseErrorKind::IndexOutOfRange => "index out of range",
+            ParseErrorKind::ReadOnlySegment => "cannot pop into segment",
+            ParseErrorKind::UnexpectedToken => "unexpected token",
+            ParseErrorKind::OutsideFunction => "command outside a function",
+            ParseErrorKind::ForeignFunction => "function of another class",
+            ParseErrorKind::DuplicateFunction => "function defined twice",
+            ParseErrorKind::Io => "cannot read source",
+        };
+        write!(f, "{}:{}:{}: {} `{}`", self.file, self.line, self.column, what, self.token)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Splits a line into its whitespace separated tokens, remembering the
+/// 1-based column each token starts at. Anything after `//` is dropped.
+pub fn tokenize(line: &str) -> Vec<(usize, &str)> {
+    let code = line.split("//").next().unwrap_or("");
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in code.char_indices().chain(Some((code.len(), ' '))) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                tokens.push((code[..s].chars().count() + 1, &code[s..i]));
+                start = None;
+            }
+            _ => (),
         }
     }
+    tokens
 }
 
-pub fn vm_commands<R: Read>(r: R) -> Vec<VmCommand> {
+/// Cursor over the tokens of a single line that turns each missing or
+/// malformed operand into a `ParseError`.
+struct LineParser<'a> {
+    file: &'a str,
+    line: usize,
+    tokens: Vec<(usize, &'a str)>,
+    next: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn error(&self, kind: ParseErrorKind, column: usize, token: &str) -> ParseError {
+        ParseError {
+            kind,
+            file: self.file.into(),
+            line: self.line,
+            column,
+            token: token.into(),
+        }
+    }
+
+    fn operand(&mut self) -> Result<(usize, &'a str), ParseError> {
+        match self.tokens.get(self.next) {
+            Some(&t) => {
+                self.next += 1;
+                Ok(t)
+            }
+            None => {
+                let (column, token) = self.tokens[self.next - 1];
+                Err(self.error(ParseErrorKind::MissingOperand, column, token))
+            }
+        }
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        self.operand().map(|(_, s)| s.into())
+    }
+
+    fn number(&mut self) -> Result<i32, ParseError> {
+        let (column, token) = self.operand()?;
+        match token.parse::<i32>() {
+            Ok(n) if n >= 0 => Ok(n),
+            _ => Err(self.error(ParseErrorKind::InvalidNumber, column, token)),
+        }
+    }
+
+    fn segment(&mut self) -> Result<Segment, ParseError> {
+        let (column, token) = self.operand()?;
+        Segment::from_string(token)
+            .ok_or_else(|| self.error(ParseErrorKind::UnknownSegment, column, token))
+    }
+
+    /// Parses the `segment index` operands of `push` and, with `pop` set,
+    /// of `pop`.
+    fn segment_index(&mut self, pop: bool) -> Result<(Segment, i32), ParseError> {
+        let segment = self.segment()?;
+        if pop && segment == Segment::CONST {
+            let (column, token) = self.tokens[self.next - 1];
+            return Err(self.error(ParseErrorKind::ReadOnlySegment, column, token));
+        }
+        let (column, token) = self.operand()?;
+        let index = token.parse::<i32>()
+            .map_err(|_| self.error(ParseErrorKind::InvalidNumber, column, token))?;
+        let limit = match segment {
+            Segment::TEMP => 8,
+            Segment::POINTER => 2,
+            // Jack constants are 15-bit; larger values are built with `neg`.
+            Segment::CONST => 32768,
+            _ => i32::MAX,
+        };
+        if index < 0 || index >= limit {
+            return Err(self.error(ParseErrorKind::IndexOutOfRange, column, token));
+        }
+        Ok((segment, index))
+    }
+
+    fn command(&mut self) -> Result<Option<VmCommand>, ParseError> {
+        let (column, keyword) = match self.tokens.first() {
+            Some(&t) => t,
+            None => return Ok(None),
+        };
+        self.next = 1;
+        let cmd = match keyword {
+            "push" => {
+                let (segment, index) = self.segment_index(false)?;
+                VmCommand::Push(segment, index)
+            }
+            "pop" => {
+                let (segment, index) = self.segment_index(true)?;
+                VmCommand::Pop(segment, index)
+            }
+            "add" => VmCommand::Add,
+            "sub" => VmCommand::Sub,
+            "neg" => VmCommand::Neg,
+            "eq" => VmCommand::Eq,
+            "gt" => VmCommand::Gt,
+            "lt" => VmCommand::Lt,
+            "and" => VmCommand::And,
+            "or" => VmCommand::Or,
+            "not" => VmCommand::Not,
+            "label" => VmCommand::Label(self.word()?),
+            "goto" => VmCommand::Goto(self.word()?),
+            "if-goto" => VmCommand::IfGoto(self.word()?),
+            "call" => {
+                let fun = self.word()?;
+                VmCommand::Call(fun, self.number()?)
+            }
+            "function" => {
+                let fun = self.word()?;
+                VmCommand::FunDef(fun, self.number()?)
+            }
+            "return" => VmCommand::Return,
+            _ => return Err(self.error(ParseErrorKind::UnknownCommand, column, keyword)),
+        };
+        if let Some(&(column, token)) = self.tokens.get(self.next) {
+            return Err(self.error(ParseErrorKind::UnexpectedToken, column, token));
+        }
+        Ok(Some(cmd))
+    }
+}
+
+/// One source line with its layout, so that it can be written back as it
+/// was read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmLine {
+    /// Whitespace before the command or comment. A line holding nothing but
+    /// whitespace keeps all of it here.
+    pub indent: String,
+    pub command: Option<Spanned<VmCommand>>,
+    /// Whitespace after the command, up to the comment if there is one.
+    pub gap: String,
+    /// The text after `//`, if the line has a comment.
+    pub comment: Option<String>,
+    /// `\n` or `\r\n`, or nothing on a last line without a line ending.
+    pub newline: String,
+}
+
+/// A VM file together with its comments, blank lines and layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmFile {
+    pub lines: Vec<VmLine>,
+}
+
+impl VmFile {
+    pub fn commands(self) -> Vec<Spanned<VmCommand>> {
+        self.lines.into_iter().filter_map(|l| l.command).collect()
+    }
+}
+
+/// Splits `line`, which still ends in its line ending if it has one, into
+/// its layout. The command is left for the parser to fill in.
+fn split_layout(line: &str) -> VmLine {
+    let ending = if line.ends_with("\r\n") {
+        2
+    } else if line.ends_with('\n') {
+        1
+    } else {
+        0
+    };
+    let (line, newline) = line.split_at(line.len() - ending);
+    let (code, comment) = match line.find("//") {
+        Some(i) => (&line[..i], Some(line[i + 2..].to_string())),
+        None => (line, None),
+    };
+    let body = code.trim_start();
+    let indent = code[..code.len() - body.len()].to_string();
+    VmLine {
+        indent,
+        command: None,
+        gap: body[body.trim_end().len()..].to_string(),
+        comment,
+        newline: newline.to_string(),
+    }
+}
+
+/// Parses the VM source of `file`, keeping comments, blank lines and layout.
+/// Parsing carries on past malformed lines, so on failure every error in the
+/// file is returned, in line order.
+pub fn parse_file(file: &str, source: &str) -> Result<VmFile, Vec<ParseError>> {
+    let name: Rc<str> = Rc::from(file);
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let mut layout = split_layout(line);
+        let mut parser = LineParser {
+            file,
+            line: index + 1,
+            tokens: tokenize(line),
+            next: 0,
+        };
+        match parser.command() {
+            Ok(cmd) => layout.command = cmd.map(|cmd| Spanned {
+                node: cmd,
+                span: Span::new(name.clone(), index + 1),
+            }),
+            Err(e) => errors.push(e),
+        }
+        lines.push(layout);
+    }
+    if errors.is_empty() {
+        Ok(VmFile { lines })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses the VM source of `file` into its commands. Parsing carries on past
+/// malformed lines, so on failure every error in the file is returned, in
+/// line order.
+pub fn parse(file: &str, source: &str) -> Result<Vec<Spanned<VmCommand>>, Vec<ParseError>> {
+    parse_file(file, source).map(VmFile::commands)
+}
+
+/// Reads all of `r` and parses it as the VM file `file`.
+pub fn vm_commands<R: Read>(file: &str, r: R) -> Result<Vec<Spanned<VmCommand>>, Vec<ParseError>> {
     let mut reader = BufReader::new(r);
     let mut s = String::new();
-    reader.read_to_string(&mut s).unwrap();
-    s.lines().filter_map(|l| VmCommand::from_line(l)).collect()
+    if let Err(e) = reader.read_to_string(&mut s) {
+        return Err(vec![ParseError {
+            kind: ParseErrorKind::Io,
+            file: file.into(),
+            line: 0,
+            column: 0,
+            token: e.to_string(),
+        }]);
+    }
+    parse(file, &s)
 }
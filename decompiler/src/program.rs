@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use parser::{parse, tokenize, ParseError, ParseErrorKind, Span, Spanned, VmCommand};
+
+/// A `function` declaration and the commands up to the next one.
+pub struct Function {
+    pub name: String,
+    pub locals: i32,
+    /// The `function` command that starts the definition.
+    pub span: Span,
+    pub body: Vec<Spanned<VmCommand>>,
+}
+
+/// One `.vm` file, which the Jack compiler emits per class. Its `static`
+/// segment is private to the file.
+pub struct ClassFile {
+    pub name: String,
+    pub path: PathBuf,
+    /// The functions defined in this file, in file order.
+    pub functions: Vec<Function>,
+}
+
+/// An error about the token `token` on `line` of `source`.
+fn error_at(kind: ParseErrorKind, file: &str, source: &str, line: usize, token: &str) -> ParseError {
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    ParseError {
+        kind,
+        file: file.to_string(),
+        line,
+        column: tokenize(text).into_iter().find(|&(_, t)| t == token).map_or(1, |(column, _)| column),
+        token: token.to_string(),
+    }
+}
+
+/// The class `function` belongs to, from the `Class.` prefix of its name.
+fn class_of_function(function: &str) -> Option<&str> {
+    function.split_once('.').map(|(class, _)| class)
+}
+
+impl ClassFile {
+    /// Parses `source` as the class file at `path`. The class is named after
+    /// the `Class.` prefix of the first function, so a `test.vm` holding
+    /// `Screen.init` defines class `Screen`, and every other function must
+    /// belong to it. A file without functions is named after its stem.
+    pub fn parse(path: &Path, source: &str) -> Result<ClassFile, Vec<ParseError>> {
+        let file = path.to_string_lossy();
+        let mut functions: Vec<Function> = Vec::new();
+        let mut errors = Vec::new();
+        for c in parse(&file, source)? {
+            match c.node {
+                VmCommand::FunDef(name, locals) => functions.push(Function {
+                    name,
+                    locals,
+                    span: c.span,
+                    body: Vec::new(),
+                }),
+                _ => match functions.last_mut() {
+                    Some(f) => f.body.push(c),
+                    None => {
+                        let line = source.lines().nth(c.span.first_line - 1).unwrap_or("");
+                        let token = tokenize(line).first().map_or("", |&(_, t)| t);
+                        errors.push(error_at(ParseErrorKind::OutsideFunction, &file, source, c.span.first_line, token));
+                    }
+                },
+            }
+        }
+        let name = match functions.first().and_then(|f| class_of_function(&f.name)) {
+            Some(class) => class.to_string(),
+            None => path.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        for f in functions.iter() {
+            if class_of_function(&f.name).is_some_and(|class| class != name) {
+                errors.push(error_at(ParseErrorKind::ForeignFunction, &file, source, f.span.first_line, &f.name));
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| e.line);
+            return Err(errors);
+        }
+        Ok(ClassFile {
+            name,
+            path: path.to_path_buf(),
+            functions,
+        })
+    }
+}
+
+/// A whole Jack program: the class files of a directory, or any list of
+/// `.vm` files.
+#[derive(Default)]
+pub struct Program {
+    pub classes: Vec<ClassFile>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, io::Error),
+    /// A directory that holds no `.vm` files.
+    NoVmFiles(PathBuf),
+    /// Every parse error of every file that failed to parse.
+    Parse(Vec<ParseError>),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            LoadError::NoVmFiles(path) => write!(f, "{}: no .vm files found", path.display()),
+            LoadError::Parse(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+fn vm_files_in(dir: &Path) -> Result<Vec<PathBuf>, LoadError> {
+    let entries = fs::read_dir(dir).map_err(|e| LoadError::Io(dir.to_path_buf(), e))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| LoadError::Io(dir.to_path_buf(), e))?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "vm") {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(LoadError::NoVmFiles(dir.to_path_buf()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+impl Program {
+    /// Loads every path in `paths`. A directory contributes all of its
+    /// `.vm` files in name order. Parse errors of all files, and functions
+    /// defined more than once, are collected before giving up, so a single
+    /// run reports every problem.
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Program, LoadError> {
+        let mut files = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            if path.is_dir() {
+                files.extend(vm_files_in(path)?);
+            } else {
+                files.push(path.to_path_buf());
+            }
+        }
+        let mut program = Program::default();
+        let mut errors = Vec::new();
+        let mut defined = HashSet::new();
+        for file in files {
+            let source = fs::read_to_string(&file).map_err(|e| LoadError::Io(file.clone(), e))?;
+            match ClassFile::parse(&file, &source) {
+                Ok(class) => {
+                    for f in class.functions.iter().filter(|f| !defined.insert(f.name.clone())) {
+                        let path = file.to_string_lossy();
+                        errors.push(error_at(ParseErrorKind::DuplicateFunction, &path, &source, f.span.first_line, &f.name));
+                    }
+                    program.classes.push(class);
+                }
+                Err(es) => errors.extend(es),
+            }
+        }
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(LoadError::Parse(errors))
+        }
+    }
+
+    /// The class file that defines `function`.
+    pub fn class_of(&self, function: &str) -> Option<&ClassFile> {
+        self.classes.iter().find(|c| c.functions.iter().any(|f| f.name == function))
+    }
+}
+
+/// Matches the function name `name` against `pattern`, in which `*` stands
+/// for any text.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(star) => {
+            let (prefix, rest) = (&pattern[..star], &pattern[star + 1..]);
+            name.starts_with(prefix) &&
+                (prefix.len()..=name.len())
+                    .filter(|&i| name.is_char_boundary(i))
+                    .any(|i| glob_match(rest, &name[i..]))
+        }
+    }
+}
+
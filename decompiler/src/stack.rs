@@ -0,0 +1,120 @@
+//! Abstract interpretation of the operand stack over a function's control-flow
+//! graph. Only the number of values on the stack is tracked, which is enough
+//! to find underflows, jumps that meet with different depths, and returns
+//! that leave values behind.
+
+use decompiler::{EdgeKind, Graph};
+use diagnostic::Diagnostic;
+use parser::{Span, Spanned, VmCommand};
+
+/// The stack depth at the start and end of every block, or `None` for
+/// blocks the analysis never reached.
+pub struct StackHeights {
+    entry: Vec<Option<usize>>,
+    exit: Vec<Option<usize>>,
+}
+
+impl StackHeights {
+    pub fn at_entry(&self, node: usize) -> Option<usize> {
+        self.entry[node]
+    }
+
+    /// The depth once the block's commands, and the pop of an `if-goto`
+    /// ending it, have run.
+    pub fn at_exit(&self, node: usize) -> Option<usize> {
+        self.exit[node]
+    }
+}
+
+/// How many values `cmd` pops and then pushes.
+fn effect(cmd: &VmCommand) -> (usize, usize) {
+    match cmd {
+        VmCommand::Push(..) => (0, 1),
+        VmCommand::Pop(..) => (1, 0),
+        VmCommand::Add | VmCommand::Sub | VmCommand::Eq | VmCommand::Gt |
+        VmCommand::Lt | VmCommand::And | VmCommand::Or => (2, 1),
+        VmCommand::Neg | VmCommand::Not => (1, 1),
+        VmCommand::Call(_, n) => (*n as usize, 1),
+        VmCommand::IfGoto(_) | VmCommand::Return => (1, 0),
+        VmCommand::Label(_) | VmCommand::Goto(_) | VmCommand::FunDef(..) => (0, 0),
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 { "value" } else { "values" }
+}
+
+/// Computes the stack depth at the entry and exit of every block of
+/// `graph`, starting from an empty stack at the entry node. Underflows,
+/// blocks reached with two different depths, and returns that do not
+/// leave the stack empty are added to `diags` as errors. A block's depth
+/// is whatever it was first reached with.
+pub fn verify(graph: &Graph<Spanned<VmCommand>>, diags: &mut Vec<Diagnostic>) -> StackHeights {
+    let n = graph.node_count();
+    let mut heights = StackHeights {
+        entry: vec![None; n],
+        exit: vec![None; n],
+    };
+    heights.entry[0] = Some(0);
+    let mut worklist = vec![0];
+    while let Some(b) = worklist.pop() {
+        let mut depth = heights.entry[b].unwrap_or(0);
+        for c in graph.commands(b) {
+            let (pops, pushes) = effect(&c.node);
+            if depth < pops {
+                diags.push(Diagnostic::error(Some(c.span.clone()), format!(
+                    "stack underflow: `{}` needs {} {} but the stack holds {}",
+                    c.node, pops, plural(pops), depth,
+                )));
+                depth = 0;
+            } else {
+                depth -= pops;
+            }
+            if c.node == VmCommand::Return && depth > 0 {
+                diags.push(Diagnostic::error(Some(c.span.clone()), format!(
+                    "`return` leaves {} {} on the stack", depth, plural(depth),
+                )));
+                depth = 0;
+            }
+            depth += pushes;
+        }
+        if graph.edges(b).iter().any(|e| e.kind == EdgeKind::CondTaken) {
+            if depth == 0 {
+                diags.push(Diagnostic::error(graph.end(b).cloned(), "stack underflow: `if-goto` on an empty stack".into()));
+            } else {
+                depth -= 1;
+            }
+        }
+        heights.exit[b] = Some(depth);
+
+        for s in graph.successors(b).filter(|&s| s != graph.exit()) {
+            match heights.entry[s] {
+                None => {
+                    heights.entry[s] = Some(depth);
+                    worklist.push(s);
+                }
+                Some(d) if d != depth => {
+                    let target = match graph.label(s) {
+                        Some(label) => format!("`{}`", label),
+                        None => format!("block {}", s),
+                    };
+                    diags.push(Diagnostic::error(location(graph, b, s), format!(
+                        "stack depth mismatch at {}: {} {} on this path but {} on another",
+                        target, depth, plural(depth), d,
+                    )));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    heights
+}
+
+/// Where to report a problem with the edge from `from` to `to`: the jump
+/// if there is one, else the nearest command.
+fn location(graph: &Graph<Spanned<VmCommand>>, from: usize, to: usize) -> Option<Span> {
+    graph.end(from)
+        .or_else(|| graph.commands(from).last().map(|c| &c.span))
+        .or_else(|| graph.commands(to).first().map(|c| &c.span))
+        .cloned()
+}
@@ -0,0 +1,718 @@
+//! Types for the variables of a decompiled program. Every variable, every
+//! subroutine result and every intermediate value is a node of a union-find
+//! structure; statements unify the nodes that must share a type, and
+//! evidence such as an OS call signature, an array access or a string
+//! constant pins a node to a type. Evidence is ranked: `int` only says a
+//! value took part in arithmetic, so `char` and `boolean` override it, and
+//! they in turn give way to the reference types `Array`, `String` and
+//! classes. Two different reference types meeting is reported and the first
+//! one kept.
+//!
+//! Only declarations come out typed: fields, statics, parameters, locals
+//! and results. Statements stay untyped IR, and the types of intermediate
+//! values are dropped once inference settles.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Display;
+use std::mem;
+
+use annotations::{Annotations, Target};
+use diagnostic::Diagnostic;
+use os_api;
+use parser::Span;
+use untyped_ir::{Signature, SubroutineKind, UnTypedClass, UnTypedIR};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    Array,
+    String,
+    Class(String),
+}
+
+impl Type {
+    /// The type a Jack declaration spells `name`.
+    pub fn from_name(name: &str) -> Type {
+        match name {
+            "int" => Type::Int,
+            "char" => Type::Char,
+            "boolean" => Type::Boolean,
+            "Array" => Type::Array,
+            "String" => Type::String,
+            class => Type::Class(class.to_string()),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Type::Int => 0,
+            Type::Char | Type::Boolean => 1,
+            Type::Array | Type::String | Type::Class(_) => 2,
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Type::Int => "int",
+            Type::Char => "char",
+            Type::Boolean => "boolean",
+            Type::Array => "Array",
+            Type::String => "String",
+            Type::Class(name) => name,
+        })
+    }
+}
+
+/// A subroutine with the types of its result and of its variables. The
+/// body is still untyped IR; look a variable up with `var_type`.
+pub struct DeclaredSubroutine {
+    pub sig: Signature,
+    /// `None` for a void subroutine.
+    pub ret: Option<Type>,
+    /// The parameters, locals and variables the decompiler made up, by IR
+    /// name such as `LCL_0`, in declaration order.
+    pub vars: Vec<(String, Type)>,
+    /// Jack names annotated for some of `vars`.
+    pub names: HashMap<String, String>,
+    pub body: Vec<UnTypedIR>,
+}
+
+impl DeclaredSubroutine {
+    pub fn var_type(&self, var: &str) -> Option<&Type> {
+        self.vars.iter().find(|(v, _)| v == var).map(|(_, ty)| ty)
+    }
+}
+
+/// A class with the types of its fields and statics, and its subroutines.
+pub struct DeclaredClass {
+    pub name: String,
+    pub statics: Vec<Type>,
+    pub fields: Vec<Type>,
+    /// Jack names annotated for some fields and statics, by IR name.
+    pub names: HashMap<String, String>,
+    pub subroutines: Vec<DeclaredSubroutine>,
+}
+
+/// The program-wide identity of a variable or result.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// A variable of one subroutine, such as `LCL_0` in `Main.main`.
+    Local(String, String),
+    /// A static variable, by its IR name `Class.STATIC_i`.
+    Static(String),
+    Field(String, usize),
+    Result(String),
+}
+
+fn index(var: &str, prefix: &str) -> Option<usize> {
+    var.strip_prefix(prefix).and_then(|i| i.parse().ok())
+}
+
+struct Inference<'a> {
+    parent: Vec<usize>,
+    types: Vec<Option<Type>>,
+    /// The annotation that fixes the type of each root, if any.
+    pins: Vec<Option<Target>>,
+    keys: HashMap<Key, usize>,
+    /// The first statement mentioning each key in the subroutine it
+    /// belongs to, to point diagnostics at.
+    spans: HashMap<Key, Span>,
+    /// The subroutines the program defines.
+    subroutines: HashSet<String>,
+    /// The nodes of each `&` and `|` and of its operands, with where it
+    /// is. Both operators are logical or bitwise depending on their
+    /// operands, so they are only unified once the rest is known.
+    logic: Vec<(usize, usize, usize, Option<Span>)>,
+    /// Array accesses of one variable by another, with the nodes of both,
+    /// left until every other piece of evidence is in to tell which is
+    /// the array.
+    undecided: Vec<(String, String, String, usize, usize, Option<Span>)>,
+    /// The accesses among them, by subroutine, base and index, whose
+    /// index turned out to be the array.
+    swapped: HashSet<(String, String, String)>,
+    diags: &'a mut Vec<Diagnostic>,
+    /// Where the subroutine being walked is.
+    class: String,
+    sub: String,
+    span: Option<Span>,
+}
+
+impl<'a> Inference<'a> {
+    fn fresh(&mut self, ty: Option<Type>) -> usize {
+        self.parent.push(self.parent.len());
+        self.types.push(ty);
+        self.pins.push(None);
+        self.parent.len() - 1
+    }
+
+    fn find(&mut self, mut n: usize) -> usize {
+        while self.parent[n] != n {
+            self.parent[n] = self.parent[self.parent[n]];
+            n = self.parent[n];
+        }
+        n
+    }
+
+    fn node(&mut self, key: Key) -> usize {
+        // A call mentions the callee's parameters and result, but a
+        // warning about them belongs in the callee.
+        let own = match key {
+            Key::Local(ref sub, _) | Key::Result(ref sub) => *sub == self.sub,
+            Key::Static(_) | Key::Field(..) => true,
+        };
+        if let (true, Some(span)) = (own, self.span.clone()) {
+            self.spans.entry(key.clone()).or_insert(span);
+        }
+        if let Some(&n) = self.keys.get(&key) {
+            return n;
+        }
+        let n = self.fresh(None);
+        self.keys.insert(key, n);
+        n
+    }
+
+    fn unify(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (pa, pb) = (self.pins[a].take(), self.pins[b].take());
+        let ty = match (self.types[a].take(), self.types[b].take()) {
+            (Some(x), Some(y)) => match (&pa, &pb) {
+                (Some(p), Some(q)) => {
+                    if x != y {
+                        self.diags.push(Diagnostic::warning(self.span.clone(), format!(
+                            "`{}` is annotated {} but `{}` {}, and they hold the same value", p, x, q, y,
+                        )));
+                    }
+                    Some(x)
+                }
+                (Some(p), None) | (None, Some(p)) => {
+                    let (pinned, other) = if pa.is_some() { (x, y) } else { (y, x) };
+                    if pinned != other && other.rank() > 0 {
+                        self.diags.push(Diagnostic::warning(self.span.clone(), format!(
+                            "`{}` is annotated {} but is used as {} here", p, pinned, other,
+                        )));
+                    }
+                    Some(pinned)
+                }
+                (None, None) => {
+                    if x.rank() == y.rank() && x != y && x.rank() > 0 {
+                        self.diags.push(Diagnostic::warning(self.span.clone(), format!(
+                            "{}: a value is used both as {} and as {}; typed {}", self.sub, x, y, x,
+                        )));
+                    }
+                    Some(if y.rank() > x.rank() { y } else { x })
+                }
+            },
+            (x, y) => x.or(y),
+        };
+        self.parent[b] = a;
+        self.types[a] = ty;
+        self.pins[a] = pa.or(pb);
+    }
+
+    /// Records that the value `n` is used as a `ty`.
+    fn hint(&mut self, n: usize, ty: Type) {
+        let t = self.fresh(Some(ty));
+        self.unify(n, t);
+    }
+
+    fn var(&mut self, v: &str) -> usize {
+        if v == "this" {
+            let class = Type::Class(self.class.clone());
+            return self.fresh(Some(class));
+        }
+        let key = if let Some(i) = index(v, "field_") {
+            Key::Field(self.class.clone(), i)
+        } else if v.contains(".STATIC_") {
+            Key::Static(v.to_string())
+        } else {
+            Key::Local(self.sub.clone(), v.to_string())
+        };
+        self.node(key)
+    }
+
+    /// Unifies the arguments of a call of `func` with its parameters and
+    /// returns the node of its result. `first` is the number of leading
+    /// parameters, such as a method's object, that `args` leaves out.
+    fn call(&mut self, func: &str, first: usize, args: &[UnTypedIR]) -> usize {
+        let args: Vec<usize> = args.iter().map(|a| self.expr(a)).collect();
+        if self.subroutines.contains(func) {
+            for (i, a) in args.into_iter().enumerate() {
+                let p = self.node(Key::Local(func.to_string(), format!("ARG_{}", first + i)));
+                self.unify(a, p);
+            }
+            return self.node(Key::Result(func.to_string()));
+        }
+        match os_api::lookup(func) {
+            Some(os) => {
+                // Any object goes where the OS takes an `Array`, as in
+                // `Memory.deAlloc(this)`, so those parameters say nothing.
+                let params = os.vm_params().into_iter().skip(first);
+                for (a, ty) in args.into_iter().zip(params).filter(|(_, ty)| *ty != Type::Array) {
+                    self.hint(a, ty);
+                }
+                self.fresh(os.ret.clone())
+            }
+            None => self.fresh(None),
+        }
+    }
+
+    fn expr(&mut self, e: &UnTypedIR) -> usize {
+        match e {
+            UnTypedIR::Spanned(_, e) => self.expr(e),
+            UnTypedIR::ConstInt(_) => self.fresh(None),
+            UnTypedIR::ConstString(_) => self.fresh(Some(Type::String)),
+            UnTypedIR::Var(v) => self.var(v),
+            UnTypedIR::Unary(op, e) => {
+                if op == "~" && *e.unspanned() == UnTypedIR::ConstInt(0) {
+                    return self.fresh(Some(Type::Boolean));
+                }
+                let n = self.expr(e);
+                if op == "-" {
+                    self.hint(n, Type::Int);
+                }
+                n
+            }
+            UnTypedIR::Binary(op, e1, e2) => {
+                let (a, b) = (self.expr(e1), self.expr(e2));
+                match op.as_str() {
+                    "&" | "|" => {
+                        let n = self.fresh(None);
+                        self.logic.push((n, a, b, self.span.clone()));
+                        n
+                    }
+                    "=" => {
+                        self.unify(a, b);
+                        self.fresh(Some(Type::Boolean))
+                    }
+                    "<" | ">" => {
+                        self.hint(a, Type::Int);
+                        self.hint(b, Type::Int);
+                        self.fresh(Some(Type::Boolean))
+                    }
+                    _ => {
+                        self.hint(a, Type::Int);
+                        self.hint(b, Type::Int);
+                        self.fresh(Some(Type::Int))
+                    }
+                }
+            }
+            UnTypedIR::Call(func, args) => self.call(func, 0, args),
+            UnTypedIR::MethodCall(obj, func, args) => {
+                let o = self.expr(obj);
+                let class = func.rsplit_once('.').map_or(func.as_str(), |(c, _)| c);
+                self.hint(o, Type::from_name(class));
+                self.call(func, 1, args)
+            }
+            UnTypedIR::ArrayOffset(base, offset) => {
+                let (b, o) = (self.expr(base), self.expr(offset));
+                if let (UnTypedIR::Var(x), UnTypedIR::Var(y)) = (base.unspanned(), offset.unspanned()) {
+                    self.undecided.push((self.sub.clone(), x.clone(), y.clone(), b, o, self.span.clone()));
+                } else {
+                    self.hint(b, Type::Array);
+                    self.hint(o, Type::Int);
+                }
+                self.fresh(None)
+            }
+            stmt => {
+                self.stmt(stmt);
+                self.fresh(None)
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &UnTypedIR) {
+        match stmt {
+            UnTypedIR::Spanned(span, s) => {
+                let outer = self.span.replace(span.clone());
+                self.stmt(s);
+                self.span = outer;
+            }
+            UnTypedIR::Assign(target, value) => {
+                let (t, v) = (self.expr(target), self.expr(value));
+                self.unify(t, v);
+            }
+            UnTypedIR::Return(e) => {
+                let n = self.expr(e);
+                let r = self.node(Key::Result(self.sub.clone()));
+                self.unify(n, r);
+            }
+            UnTypedIR::Do(e) => {
+                self.expr(e);
+            }
+            UnTypedIR::If(cond, taken, not_taken, cont) => {
+                let c = self.expr(cond);
+                self.hint(c, Type::Boolean);
+                for s in taken.iter().chain(not_taken.iter()).chain(cont.iter()) {
+                    self.stmt(s);
+                }
+            }
+            UnTypedIR::While(cond, body, cont) => {
+                let c = self.expr(cond);
+                self.hint(c, Type::Boolean);
+                for s in body.iter().chain(cont.iter()) {
+                    self.stmt(s);
+                }
+            }
+            UnTypedIR::ReturnVoid | UnTypedIR::FuncDef(..) => {}
+            e => {
+                self.expr(e);
+            }
+        }
+    }
+
+    /// Makes every `&` and `|` with a boolean operand logical: its result
+    /// and both operands share their type. The others are left bitwise,
+    /// so `x & 1` says nothing about `x`. One becoming logical can make
+    /// another one's operand boolean, so this runs until nothing changes.
+    fn settle_logic(&mut self) {
+        let mut logic = mem::take(&mut self.logic);
+        loop {
+            let before = logic.len();
+            logic.retain(|&(n, a, b, ref span)| {
+                let boolean = |inference: &mut Inference, n| {
+                    let root = inference.find(n);
+                    inference.types[root] == Some(Type::Boolean)
+                };
+                if !boolean(self, a) && !boolean(self, b) {
+                    return true;
+                }
+                self.span = span.clone();
+                self.unify(n, a);
+                self.unify(n, b);
+                false
+            });
+            if logic.len() == before {
+                break;
+            }
+        }
+        self.span = None;
+    }
+
+    /// Settles which side of each undecided access is the array: the base
+    /// unless only the index has a reference type, such as a variable
+    /// assigned `Array.new(n)`.
+    fn settle_arrays(&mut self) {
+        for (sub, base, offset, b, o, span) in mem::take(&mut self.undecided) {
+            let mut reference = |n: usize| {
+                let root = self.find(n);
+                self.types[root].as_ref().is_some_and(|t| t.rank() == 2)
+            };
+            let (b, o) = if reference(o) && !reference(b) {
+                self.swapped.insert((sub, base, offset));
+                (o, b)
+            } else {
+                (b, o)
+            };
+            self.span = span;
+            self.hint(b, Type::Array);
+            self.hint(o, Type::Int);
+        }
+        self.span = None;
+    }
+
+    /// The type found for `key`, or `int` if nothing says, with a warning
+    /// unless the decompiler made the variable up.
+    fn resolve(&mut self, key: Key, what: &str) -> Type {
+        let ty = match self.keys.get(&key).cloned() {
+            Some(n) => {
+                let root = self.find(n);
+                self.types[root].clone()
+            }
+            None => None,
+        };
+        ty.unwrap_or_else(|| {
+            if let Key::Local(_, ref v) = key {
+                if is_synthetic(v) {
+                    return Type::Int;
+                }
+            }
+            self.diags.push(Diagnostic::warning(self.spans.get(&key).cloned(), format!(
+                "cannot infer the type of {}; declared int", what,
+            )));
+            Type::Int
+        })
+    }
+}
+
+/// The variables `body` declares or uses in its own scope, in order:
+/// parameters, locals, then the variables the decompiler made up.
+fn scope_vars(sig: &Signature, body: &[UnTypedIR]) -> Vec<String> {
+    let mut locals = sig.locals;
+    let mut others: Vec<String> = Vec::new();
+    for stmt in body.iter() {
+        stmt.visit_vars(&mut |v| {
+            if let Some(i) = index(v, "LCL_") {
+                locals = locals.max(i + 1);
+            } else if is_scoped(v) && !others.iter().any(|o| o == v) {
+                others.push(v.to_string());
+            }
+        });
+    }
+    let mut vars = sig.declared_params();
+    vars.extend((0..locals).map(|i| format!("LCL_{}", i)));
+    vars.extend(others);
+    vars
+}
+
+/// Whether `v` is a variable of its subroutine other than a parameter or
+/// local. Segments reached through `pointer` are memory, not variables.
+fn is_scoped(v: &str) -> bool {
+    !v.contains('.') && v != "this" &&
+        !["field_", "ARG_", "LCL_", "POINTER_", "THIS_", "THAT_"].iter().any(|p| v.starts_with(p))
+}
+
+/// Stores the value of every statement that is a bare expression in
+/// `TEMP_0`, as the VM code does with values it drops, since Jack has no
+/// expression statements. Calls stay as they are and become `do`.
+fn store_bare_values(stmt: UnTypedIR) -> UnTypedIR {
+    let store = |stmts: Vec<UnTypedIR>| stmts.into_iter().map(|s| match s.unspanned() {
+        UnTypedIR::ConstInt(_) | UnTypedIR::ConstString(_) | UnTypedIR::Var(_) |
+        UnTypedIR::Unary(..) | UnTypedIR::Binary(..) | UnTypedIR::ArrayOffset(..) => {
+            let (span, e) = match s {
+                UnTypedIR::Spanned(span, e) => (Some(span), *e),
+                e => (None, e),
+            };
+            let temp = UnTypedIR::Var("TEMP_0".into());
+            let stmt = UnTypedIR::Assign(Box::new(temp), Box::new(e));
+            match span {
+                Some(span) => UnTypedIR::Spanned(span, Box::new(stmt)),
+                None => stmt,
+            }
+        }
+        _ => s,
+    }).collect();
+    stmt.transform(&|e| match e {
+        UnTypedIR::FuncDef(sig, body) => UnTypedIR::FuncDef(sig, store(body)),
+        UnTypedIR::If(c, ts, fs, conts) => UnTypedIR::If(c, store(ts), store(fs), store(conts)),
+        UnTypedIR::While(c, body, conts) => UnTypedIR::While(c, store(body), store(conts)),
+        e => e,
+    })
+}
+
+/// Whether the decompiler made up `v`: a value carried between blocks, a
+/// loop flag, the state of a state machine or a `temp` register.
+fn is_synthetic(v: &str) -> bool {
+    v == "STATE" || ["STACK_", "LOOP_", "TEMP_"].iter().any(|p| v.starts_with(p))
+}
+
+/// The inference key of an annotated variable.
+fn target_key(target: &Target) -> Key {
+    match target {
+        Target::Local(sub, _) | Target::Argument(sub, _) => Key::Local(sub.clone(), target.ir_name().unwrap()),
+        Target::Field(class, i) => Key::Field(class.clone(), *i),
+        Target::Static(..) => Key::Static(target.ir_name().unwrap()),
+        Target::Result(sub) => Key::Result(sub.clone()),
+    }
+}
+
+/// Infers the types of every variable, field, static and subroutine result
+/// of the program. The types in `annotations` hold whatever the evidence
+/// says, which is warned about if it disagrees. Whatever neither covers
+/// becomes `int`, with a warning in `diags`.
+pub fn infer(classes: Vec<UnTypedClass>, annotations: &Annotations, diags: &mut Vec<Diagnostic>) -> Vec<DeclaredClass> {
+    let classes: Vec<UnTypedClass> = classes.into_iter().map(|c| UnTypedClass {
+        subroutines: c.subroutines.into_iter().map(store_bare_values).collect(),
+        ..c
+    }).collect();
+    let mut inference = Inference {
+        parent: Vec::new(),
+        types: Vec::new(),
+        pins: Vec::new(),
+        keys: HashMap::new(),
+        spans: HashMap::new(),
+        subroutines: HashSet::new(),
+        logic: Vec::new(),
+        undecided: Vec::new(),
+        swapped: HashSet::new(),
+        diags,
+        class: String::new(),
+        sub: String::new(),
+        span: None,
+    };
+    for s in classes.iter().flat_map(|c| c.subroutines.iter()) {
+        if let UnTypedIR::FuncDef(sig, _) = s {
+            inference.subroutines.insert(sig.name.clone());
+        }
+    }
+    let mut pinned: Vec<(&Target, &(Type, Span))> = annotations.types.iter().collect();
+    pinned.sort_by_key(|(_, (_, span))| span.first_line);
+    for (target, (ty, span)) in pinned {
+        let sig = |name: &str| classes.iter().flat_map(|c| c.subroutines.iter()).find_map(|s| match s {
+            UnTypedIR::FuncDef(sig, _) if sig.name == name => Some(sig),
+            _ => None,
+        });
+        let class = |name: &str| classes.iter().find(|c| c.name == name);
+        let known = match target {
+            Target::Local(sub, i) => sig(sub).is_some_and(|s| *i < s.locals),
+            Target::Argument(sub, i) => sig(sub).is_some_and(|s| *i < s.params),
+            Target::Field(c, i) => class(c).is_some_and(|c| *i < c.fields),
+            Target::Static(c, _) => class(c).is_some(),
+            Target::Result(sub) => sig(sub).is_some(),
+        };
+        if let Target::Result(sub) = target {
+            if sig(sub).is_some_and(|s| s.void) {
+                inference.diags.push(Diagnostic::warning(Some(span.clone()), format!(
+                    "{} returns nothing; `{}` is ignored", sub, target,
+                )));
+                continue;
+            }
+        }
+        if !known {
+            inference.diags.push(Diagnostic::warning(Some(span.clone()), format!(
+                "`{}` is not in the program", target,
+            )));
+            continue;
+        }
+        if let Type::Class(ref name) = *ty {
+            if class(name).is_none() && !os_api::OS_API.iter().any(|os| os.class() == name) {
+                inference.diags.push(Diagnostic::warning(Some(span.clone()), format!(
+                    "`{}` is not a class of the program or the OS; `{}` is typed by the evidence", name, target,
+                )));
+                continue;
+            }
+        }
+        let n = inference.node(target_key(target));
+        let t = inference.fresh(Some(ty.clone()));
+        inference.pins[t] = Some(target.clone());
+        inference.unify(n, t);
+    }
+    for class in classes.iter() {
+        for s in class.subroutines.iter() {
+            if let UnTypedIR::FuncDef(sig, body) = s {
+                inference.class = class.name.clone();
+                inference.sub = sig.name.clone();
+                if sig.kind == SubroutineKind::Constructor {
+                    let r = inference.node(Key::Result(sig.name.clone()));
+                    inference.hint(r, Type::Class(class.name.clone()));
+                }
+                for stmt in body.iter() {
+                    inference.stmt(stmt);
+                }
+            }
+        }
+    }
+    inference.settle_logic();
+    inference.settle_arrays();
+    classes.into_iter().map(|class| inference.class_types(class, annotations)).collect()
+}
+
+/// The Jack name of the variable `v` of `class` when no annotation names
+/// it. Variables reached through `pointer` have no name.
+pub fn generated_name(class: &str, v: &str) -> String {
+    if let Some(i) = index(v, &format!("{}.STATIC_", class)) {
+        format!("static_{}", i)
+    } else if let Some(i) = index(v, "LCL_") {
+        format!("local_{}", i)
+    } else if let Some(i) = index(v, "ARG_") {
+        format!("arg_{}", i)
+    } else {
+        v.to_lowercase()
+    }
+}
+
+/// The Jack names `annotations` gives to `vars`, the variables of one
+/// scope of `class`, by IR name. `scope` tells which targets are in the
+/// scope. A name already in use, by another variable of the scope or in
+/// `outer`, is refused and its variable keeps its own name.
+fn names_in(
+    annotations: &Annotations,
+    class: &str,
+    vars: &[String],
+    scope: &dyn Fn(&Target) -> bool,
+    outer: &HashSet<String>,
+    diags: &mut Vec<Diagnostic>,
+) -> HashMap<String, String> {
+    let mut named: Vec<(&Target, String, &(String, Span))> = annotations.names.iter()
+        .filter(|(t, _)| scope(t))
+        .filter_map(|(t, n)| Some((t, t.ir_name()?, n)))
+        .collect();
+    named.sort_by_key(|(_, _, (_, span))| span.first_line);
+    // Any variable may end up with its generated name, should its own
+    // annotation be refused.
+    let mut taken = outer.clone();
+    taken.extend(vars.iter().map(|v| generated_name(class, v)));
+    let mut names: HashMap<String, String> = HashMap::new();
+    for (target, v, (name, span)) in named {
+        if taken.contains(name) && *name != generated_name(class, &v) {
+            diags.push(Diagnostic::warning(Some(span.clone()), format!(
+                "`{}` already names another variable; `{}` keeps its own name", name, target,
+            )));
+        } else {
+            taken.insert(name.clone());
+            names.insert(v, name.clone());
+        }
+    }
+    names
+}
+
+impl<'a> Inference<'a> {
+    fn is_swapped(&self, sub: &str, base: &UnTypedIR, offset: &UnTypedIR) -> bool {
+        match (base.unspanned(), offset.unspanned()) {
+            (UnTypedIR::Var(x), UnTypedIR::Var(y)) => self.swapped.contains(&(sub.to_string(), x.clone(), y.clone())),
+            _ => false,
+        }
+    }
+
+    fn class_types(&mut self, class: UnTypedClass, annotations: &Annotations) -> DeclaredClass {
+        let prefix = format!("{}.STATIC_", class.name);
+        let mut statics = 0;
+        for s in class.subroutines.iter() {
+            s.visit_vars(&mut |v| if let Some(i) = index(v, &prefix) {
+                statics = statics.max(i + 1);
+            });
+        }
+        let members: Vec<String> = (0..statics).map(|i| format!("{}{}", prefix, i))
+            .chain((0..class.fields).map(|i| format!("field_{}", i)))
+            .collect();
+        let statics = (0..statics)
+            .map(|i| self.resolve(Key::Static(format!("{}{}", prefix, i)), &format!("static {} of {}", i, class.name)))
+            .collect();
+        let fields = (0..class.fields)
+            .map(|i| self.resolve(Key::Field(class.name.clone(), i), &format!("field {} of {}", i, class.name)))
+            .collect();
+        let names = names_in(annotations, &class.name, &members, &|t| match t {
+            Target::Field(c, _) | Target::Static(c, _) => *c == class.name,
+            _ => false,
+        }, &HashSet::new(), self.diags);
+        // Locals and parameters must not hide the class's own variables.
+        let outer: HashSet<String> = members.iter()
+            .map(|v| names.get(v).cloned().unwrap_or_else(|| generated_name(&class.name, v)))
+            .collect();
+        let class_name = class.name.clone();
+        let subroutines = class.subroutines.into_iter().filter_map(|s| match s {
+            UnTypedIR::FuncDef(sig, body) => {
+                let vars: Vec<(String, Type)> = scope_vars(&sig, &body).into_iter().map(|v| {
+                    let ty = self.resolve(Key::Local(sig.name.clone(), v.clone()), &format!("`{}` in {}", v, sig.name));
+                    (v, ty)
+                }).collect();
+                let ret = if sig.void {
+                    None
+                } else {
+                    Some(self.resolve(Key::Result(sig.name.clone()), &format!("the result of {}", sig.name)))
+                };
+                let scoped: Vec<String> = vars.iter().map(|(v, _)| v.clone()).collect();
+                let names = names_in(annotations, &class_name, &scoped, &|t| match t {
+                    Target::Local(sub, _) | Target::Argument(sub, _) => *sub == sig.name,
+                    _ => false,
+                }, &outer, self.diags);
+                let body = body.into_iter().map(|s| s.transform(&|e| match e {
+                    UnTypedIR::ArrayOffset(base, offset) if self.is_swapped(&sig.name, &base, &offset) => {
+                        UnTypedIR::ArrayOffset(offset, base)
+                    }
+                    e => e,
+                })).collect();
+                Some(DeclaredSubroutine { sig, ret, vars, names, body })
+            }
+            _ => None,
+        }).collect();
+        DeclaredClass { name: class.name, statics, fields, names, subroutines }
+    }
+}
@@ -1,123 +1,276 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::mem;
 use std::fmt::Display;
 
-use parser::{VmCommand, Segment};
+use os_api;
+use parser::{VmCommand, Segment, Span, Spanned};
 
-#[derive(Debug, Clone)]
+/// What kind of Jack subroutine a VM function was compiled from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubroutineKind {
+    Constructor,
+    Method,
+    Function,
+}
+
+impl Display for SubroutineKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Method => "method",
+            SubroutineKind::Function => "function",
+        })
+    }
+}
+
+/// The declaration of a decompiled subroutine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub kind: SubroutineKind,
+    /// The full VM name, such as `Main.main`.
+    pub name: String,
+    /// The number of arguments the VM function takes, counting the object
+    /// of a method.
+    pub params: usize,
+    pub locals: usize,
+    /// Whether the subroutine returns nothing.
+    pub void: bool,
+}
+
+impl Signature {
+    pub fn function(name: String, params: usize, locals: usize) -> Self {
+        Signature { kind: SubroutineKind::Function, name, params, locals, void: false }
+    }
+
+    /// The arguments declared in Jack; a method's argument 0 is `this`.
+    pub fn declared_params(&self) -> Vec<String> {
+        let first = if self.kind == SubroutineKind::Method { 1 } else { 0 };
+        (first..self.params.max(first)).map(|i| format!("ARG_{}", i)).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum UnTypedIR {
-    FuncDef(String, Vec<UnTypedIR>),
+    FuncDef(Signature, Vec<UnTypedIR>),
     ConstInt(i32),
     ConstString(String),
     Var(String),
     Unary(String, Box<UnTypedIR>),
     Binary(String, Box<UnTypedIR>, Box<UnTypedIR>),
     Call(String, Vec<UnTypedIR>),
+    /// A call of the method with the full name given on an object, with the
+    /// object taken out of the arguments.
+    MethodCall(Box<UnTypedIR>, String, Vec<UnTypedIR>),
     Assign(Box<UnTypedIR>, Box<UnTypedIR>),
     Return(Box<UnTypedIR>),
+    /// `return;` from a void subroutine, which the VM spells `return 0`.
+    ReturnVoid,
+    /// A call whose result is thrown away.
+    Do(Box<UnTypedIR>),
     If(Box<UnTypedIR>, Vec<UnTypedIR>, Vec<UnTypedIR>, Vec<UnTypedIR>),
     While(Box<UnTypedIR>, Vec<UnTypedIR>, Vec<UnTypedIR>),
     ArrayOffset(Box<UnTypedIR>, Box<UnTypedIR>),
+    /// A statement annotated with the VM lines it was decompiled from. Its
+    /// sub-expressions share the span.
+    Spanned(Span, Box<UnTypedIR>),
 }
 
 impl UnTypedIR {
     pub fn is_const_int(&self) -> bool {
-        match self {
-            &UnTypedIR::ConstInt(_) => true,
-            _ => false,
-        }
+        matches!(self, UnTypedIR::ConstInt(_))
     }
 
     pub fn is_const_string(&self) -> bool {
-        match self {
-            &UnTypedIR::ConstString(_) => true,
-            _ => false,
-        }
+        matches!(self, UnTypedIR::ConstString(_))
     }
 
     pub fn int(&self) -> i32 {
         match self {
-            &UnTypedIR::ConstInt(i) => i,
+            UnTypedIR::ConstInt(i) => *i,
             _ => panic!("{} is not int!", self)
         }
     }
 
     pub fn str(&self) -> &str {
         match self {
-            &UnTypedIR::ConstString(ref s) => s,
+            UnTypedIR::ConstString(s) => s,
             _ => panic!("{} is not string!", self),
         }
     }
 
     pub fn is_const_funcall(&self, func: &str, n: usize) -> bool {
         match self {
-            &UnTypedIR::Call(ref s, ref args) => {
+            UnTypedIR::Call(s, args) => {
                 s == func && args.len() == n && args.iter().all(|a| a.is_const_int() || a.is_const_string())
             }
             _ => false,
         }
     }
 
+    /// The span this node was decompiled from, if it carries one.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            UnTypedIR::Spanned(span, _) => Some(span),
+            _ => None,
+        }
+    }
+
+    /// The node with its span annotation, if any, stripped.
+    pub fn unspanned(&self) -> &UnTypedIR {
+        match self {
+            UnTypedIR::Spanned(_, e) => e.unspanned(),
+            e => e,
+        }
+    }
+
+    /// The array element at the address `self`: `i + a` becomes `a[i]`
+    /// and any other address `p` becomes `p[0]`. Jack only indexes
+    /// variables, so a variable added to anything else is the base. When
+    /// both sides are variables the operands are taken in the Jack
+    /// compiler's order, index first, and type inference may swap them.
     pub fn to_array_offset(self) -> Self {
         match self {
-            UnTypedIR::Binary(op, e1, e2) => {
-                if op == "+" {
-                    UnTypedIR::ArrayOffset(e1, e2)
-                } else {
-                    panic!("array offset only contains + sign")
+            UnTypedIR::Spanned(_, e) => e.to_array_offset(),
+            UnTypedIR::Binary(ref op, _, _) if op == "+" => match self {
+                UnTypedIR::Binary(_, left, right) => {
+                    let var = |e: &UnTypedIR| matches!(e.unspanned(), UnTypedIR::Var(_));
+                    if var(&left) && !var(&right) {
+                        UnTypedIR::ArrayOffset(left, right)
+                    } else {
+                        UnTypedIR::ArrayOffset(right, left)
+                    }
                 }
-            }
-            UnTypedIR::Var(v) => UnTypedIR::ArrayOffset(Box::new(UnTypedIR::Var(v)), Box::new(UnTypedIR::ConstInt(0))),
-            UnTypedIR::Assign(_, e) => e.to_array_offset(),
-            _ => panic!("cannot get array offset from: {:?}", self),
+                _ => unreachable!(),
+            },
+            e => UnTypedIR::ArrayOffset(Box::new(e), Box::new(UnTypedIR::ConstInt(0))),
+        }
+    }
+
+    /// Whether evaluating `self` reads array memory.
+    pub fn reads_memory(&self) -> bool {
+        match self {
+            UnTypedIR::ArrayOffset(..) => true,
+            UnTypedIR::Call(_, args) => args.iter().any(UnTypedIR::reads_memory),
+            UnTypedIR::MethodCall(obj, _, args) => obj.reads_memory() || args.iter().any(UnTypedIR::reads_memory),
+            UnTypedIR::Binary(_, e1, e2) => e1.reads_memory() || e2.reads_memory(),
+            UnTypedIR::Unary(_, e) | UnTypedIR::Spanned(_, e) => e.reads_memory(),
+            UnTypedIR::Var(v) => v.starts_with("THAT_") || v.starts_with("THIS_"),
+            _ => false,
         }
     }
 
     pub fn is_assigned_to(&self, var: &str) -> bool {
         match self {
-            &UnTypedIR::Var(ref s) => s == var,
-            &UnTypedIR::Assign(ref s, _) =>  {
+            UnTypedIR::Var(s) => s == var,
+            UnTypedIR::Assign(s, _) =>  {
                 match s.as_ref() {
-                    &UnTypedIR::Var(ref v) => v == var,
+                    UnTypedIR::Var(v) => v == var,
                     _ => false,
                 }
             }
+            UnTypedIR::Spanned(_, e) => e.is_assigned_to(var),
             _ => false,
         }
     }
 
     pub fn has_use(&self, var: &str) -> bool {
+        self.uses_any(&|v| v == var)
+    }
+
+    /// Calls `f` with every variable `self` mentions, in order, as often
+    /// as it is mentioned.
+    pub fn visit_vars(&self, f: &mut dyn FnMut(&str)) {
+        if let UnTypedIR::Var(v) = self {
+            f(v);
+        }
+        for c in self.children() {
+            c.visit_vars(f);
+        }
+    }
+
+    /// Whether any variable `self` mentions satisfies `pred`.
+    pub fn uses_any(&self, pred: &dyn Fn(&str) -> bool) -> bool {
+        let any = |irs: &[UnTypedIR]| irs.iter().any(|i| i.uses_any(pred));
         match self {
-            &UnTypedIR::Call(_, ref args) => args.iter().any(|i| i.has_use(var)),
-            &UnTypedIR::Assign(ref s, ref expr) => s.has_use(var) || expr.has_use(var),
-            &UnTypedIR::Binary(_, ref e1, ref e2) => e1.has_use(var) || e2.has_use(var),
-            &UnTypedIR::ConstInt(_) => false,
-            &UnTypedIR::ConstString(_) => false,
-            &UnTypedIR::FuncDef(_, ref exprs) => exprs.iter().any(|i| i.has_use(var)),
-            &UnTypedIR::Unary(_, ref e) => e.has_use(var),
-            &UnTypedIR::Var(ref v) => v == var,
-            &UnTypedIR::If(ref c, ref ts, ref fs, ref conts) => c.has_use(var) || ts.iter().any(|i| i.has_use(var)) || fs.iter().any(|i| i.has_use(var)) || conts.iter().any(|i| i.has_use(var)),
-            &UnTypedIR::While(ref c, ref bs, ref conts) => c.has_use(var) || bs.iter().any(|i| i.has_use(var)) || conts.iter().any(|i| i.has_use(var)),
-            &UnTypedIR::Return(ref e) => e.has_use(var),
-            &UnTypedIR::ArrayOffset(ref base, ref offset) => base.has_use(var) || offset.has_use(var),
+            UnTypedIR::Call(_, args) => any(args),
+            UnTypedIR::MethodCall(obj, _, args) => obj.uses_any(pred) || any(args),
+            UnTypedIR::Assign(s, expr) => s.uses_any(pred) || expr.uses_any(pred),
+            UnTypedIR::Binary(_, e1, e2) => e1.uses_any(pred) || e2.uses_any(pred),
+            UnTypedIR::ConstInt(_) | UnTypedIR::ReturnVoid => false,
+            UnTypedIR::ConstString(_) => false,
+            UnTypedIR::FuncDef(_, exprs) => any(exprs),
+            UnTypedIR::Unary(_, e) => e.uses_any(pred),
+            UnTypedIR::Var(v) => pred(v),
+            UnTypedIR::If(c, ts, fs, conts) => c.uses_any(pred) || any(ts) || any(fs) || any(conts),
+            UnTypedIR::While(c, bs, conts) => c.uses_any(pred) || any(bs) || any(conts),
+            UnTypedIR::Return(e) | UnTypedIR::Do(e) => e.uses_any(pred),
+            UnTypedIR::ArrayOffset(base, offset) => base.uses_any(pred) || offset.uses_any(pred),
+            UnTypedIR::Spanned(_, e) => e.uses_any(pred),
         }
     }
 
     pub fn replace_var(self, var: &str, exp: &UnTypedIR) -> Self {
+        self.map_vars(&|v| if v == var { Some(exp.clone()) } else { None })
+    }
+
+    /// Replaces every variable `v` for which `f` returns an expression.
+    pub fn map_vars(self, f: &dyn Fn(&str) -> Option<UnTypedIR>) -> Self {
+        let map = |irs: Vec<UnTypedIR>| irs.into_iter().map(|i| i.map_vars(f)).collect();
         match self {
-            UnTypedIR::Call(s, args) => UnTypedIR::Call(s, args.into_iter().map(|i| i.replace_var(var, exp)).collect()),
-            UnTypedIR::Assign(v, expr) => UnTypedIR::Assign(Box::new(v.replace_var(var, exp)), Box::new(expr.replace_var(var, exp))),
-            UnTypedIR::Binary(op, e1, e2) => UnTypedIR::Binary(op, Box::new(e1.replace_var(var, exp)), Box::new(e2.replace_var(var, exp))),
-            UnTypedIR::FuncDef(func, exprs) => UnTypedIR::FuncDef(func, exprs.into_iter().map(|i| i.replace_var(var, exp)).collect()),
-            UnTypedIR::Unary(op, e) => UnTypedIR::Unary(op, Box::new(e.replace_var(var, exp))),
-            UnTypedIR::Var(v) => if v == var { exp.clone() } else { UnTypedIR::Var(v) },
-            UnTypedIR::Return(e) => UnTypedIR::Return(Box::new(e.replace_var(var, exp))),
-            UnTypedIR::If(c,  ts,  fs, conts) => UnTypedIR::If(Box::new(c.replace_var(var, exp)), ts.into_iter().map(|i| i.replace_var(var, exp)).collect(), fs.into_iter().map(|i| i.replace_var(var, exp)).collect(), conts.into_iter().map(|i| i.replace_var(var, exp)).collect()),
-            UnTypedIR::While(c, bs, conts) => UnTypedIR::While(Box::new(c.replace_var(var, exp)), bs.into_iter().map(|i| i.replace_var(var, exp)).collect(), conts.into_iter().map(|i| i.replace_var(var, exp)).collect()),
-            UnTypedIR::ArrayOffset(base, offset) => UnTypedIR::ArrayOffset(base, Box::new(offset.replace_var(var, exp))),
+            UnTypedIR::Call(s, args) => UnTypedIR::Call(s, map(args)),
+            UnTypedIR::MethodCall(obj, s, args) => UnTypedIR::MethodCall(Box::new(obj.map_vars(f)), s, map(args)),
+            UnTypedIR::Assign(v, expr) => UnTypedIR::Assign(Box::new(v.map_vars(f)), Box::new(expr.map_vars(f))),
+            UnTypedIR::Binary(op, e1, e2) => UnTypedIR::Binary(op, Box::new(e1.map_vars(f)), Box::new(e2.map_vars(f))),
+            UnTypedIR::FuncDef(func, exprs) => UnTypedIR::FuncDef(func, map(exprs)),
+            UnTypedIR::Unary(op, e) => UnTypedIR::Unary(op, Box::new(e.map_vars(f))),
+            UnTypedIR::Var(v) => f(&v).unwrap_or(UnTypedIR::Var(v)),
+            UnTypedIR::Return(e) => UnTypedIR::Return(Box::new(e.map_vars(f))),
+            UnTypedIR::Do(e) => UnTypedIR::Do(Box::new(e.map_vars(f))),
+            UnTypedIR::If(c, ts, fs, conts) => UnTypedIR::If(Box::new(c.map_vars(f)), map(ts), map(fs), map(conts)),
+            UnTypedIR::While(c, bs, conts) => UnTypedIR::While(Box::new(c.map_vars(f)), map(bs), map(conts)),
+            UnTypedIR::ArrayOffset(base, offset) => UnTypedIR::ArrayOffset(Box::new(base.map_vars(f)), Box::new(offset.map_vars(f))),
+            UnTypedIR::Spanned(span, e) => UnTypedIR::Spanned(span, Box::new(e.map_vars(f))),
             i => i,
         }
     }
 
+    /// The nodes directly below `self`.
+    pub fn children(&self) -> Vec<&UnTypedIR> {
+        match self {
+            UnTypedIR::FuncDef(_, body) => body.iter().collect(),
+            UnTypedIR::Unary(_, e) | UnTypedIR::Return(e) | UnTypedIR::Do(e) | UnTypedIR::Spanned(_, e) => vec![e],
+            UnTypedIR::Binary(_, e1, e2) | UnTypedIR::Assign(e1, e2) | UnTypedIR::ArrayOffset(e1, e2) => vec![e1, e2],
+            UnTypedIR::Call(_, args) => args.iter().collect(),
+            UnTypedIR::MethodCall(obj, _, args) => Some(&**obj).into_iter().chain(args.iter()).collect(),
+            UnTypedIR::If(c, ts, fs, conts) => Some(&**c).into_iter().chain(ts.iter()).chain(fs.iter()).chain(conts.iter()).collect(),
+            UnTypedIR::While(c, body, conts) => Some(&**c).into_iter().chain(body.iter()).chain(conts.iter()).collect(),
+            UnTypedIR::ConstInt(_) | UnTypedIR::ConstString(_) | UnTypedIR::Var(_) | UnTypedIR::ReturnVoid => Vec::new(),
+        }
+    }
+
+    /// Rebuilds the tree bottom up, passing every node to `f` once its
+    /// children have been rebuilt.
+    pub fn transform(self, f: &dyn Fn(UnTypedIR) -> UnTypedIR) -> Self {
+        let map = |irs: Vec<UnTypedIR>| irs.into_iter().map(|i| i.transform(f)).collect();
+        let node = match self {
+            UnTypedIR::FuncDef(sig, body) => UnTypedIR::FuncDef(sig, map(body)),
+            UnTypedIR::Unary(op, e) => UnTypedIR::Unary(op, Box::new(e.transform(f))),
+            UnTypedIR::Binary(op, e1, e2) => UnTypedIR::Binary(op, Box::new(e1.transform(f)), Box::new(e2.transform(f))),
+            UnTypedIR::Call(func, args) => UnTypedIR::Call(func, map(args)),
+            UnTypedIR::MethodCall(obj, func, args) => UnTypedIR::MethodCall(Box::new(obj.transform(f)), func, map(args)),
+            UnTypedIR::Assign(v, e) => UnTypedIR::Assign(Box::new(v.transform(f)), Box::new(e.transform(f))),
+            UnTypedIR::Return(e) => UnTypedIR::Return(Box::new(e.transform(f))),
+            UnTypedIR::Do(e) => UnTypedIR::Do(Box::new(e.transform(f))),
+            UnTypedIR::If(c, ts, fs, conts) => UnTypedIR::If(Box::new(c.transform(f)), map(ts), map(fs), map(conts)),
+            UnTypedIR::While(c, bs, conts) => UnTypedIR::While(Box::new(c.transform(f)), map(bs), map(conts)),
+            UnTypedIR::ArrayOffset(base, offset) => UnTypedIR::ArrayOffset(Box::new(base.transform(f)), Box::new(offset.transform(f))),
+            UnTypedIR::Spanned(span, e) => UnTypedIR::Spanned(span, Box::new(e.transform(f))),
+            leaf => leaf,
+        };
+        f(node)
+    }
+
     pub fn reconstruct_const_string(self) -> Self {
         match self {
             UnTypedIR::FuncDef(s, irs) => {
@@ -128,12 +281,13 @@ impl UnTypedIR {
             UnTypedIR::Call(s, irs) => {
                 if s == "String.appendChar" && irs.len() == 2 {
                     let reconstructed: Vec<UnTypedIR> = irs.into_iter().map(|i| i.reconstruct_const_string()).collect();
-                    if reconstructed[1].is_const_int() && reconstructed[0].is_const_string() {
-                        return UnTypedIR::ConstString(format!("{}{}", reconstructed[0].str(), reconstructed[1].int() as u8 as char));
-                    }
-                    if reconstructed[0].is_const_funcall("String.new", 1)  {
-                        if reconstructed[1].is_const_int() {
-                            return UnTypedIR::ConstString(format!("{}", reconstructed[1].int() as u8 as char));
+                    let c = if reconstructed[1].is_const_int() { string_char(reconstructed[1].int()) } else { None };
+                    if let Some(c) = c {
+                        if reconstructed[0].is_const_string() {
+                            return UnTypedIR::ConstString(format!("{}{}", reconstructed[0].str(), c));
+                        }
+                        if reconstructed[0].is_const_funcall("String.new", 1) {
+                            return UnTypedIR::ConstString(c.to_string());
                         }
                     }
                     UnTypedIR::Call(s, reconstructed)
@@ -141,8 +295,13 @@ impl UnTypedIR {
                     UnTypedIR::Call(s, irs.into_iter().map(|i| i.reconstruct_const_string()).collect())
                 }
             }
+            UnTypedIR::MethodCall(obj, s, irs) => UnTypedIR::MethodCall(
+                Box::new(obj.reconstruct_const_string()),
+                s,
+                irs.into_iter().map(|i| i.reconstruct_const_string()).collect()),
             UnTypedIR::Assign(v, ir) => UnTypedIR::Assign(v, Box::new(ir.reconstruct_const_string())),
             UnTypedIR::Return(ir) => UnTypedIR::Return(Box::new(ir.reconstruct_const_string())),
+            UnTypedIR::Do(ir) => UnTypedIR::Do(Box::new(ir.reconstruct_const_string())),
             UnTypedIR::If(cond, true_exprs, false_exprs, conts) => 
                 UnTypedIR::If(Box::new(cond.reconstruct_const_string()), 
                     true_exprs.into_iter().map(|i| i.reconstruct_const_string()).collect(), 
@@ -152,158 +311,604 @@ impl UnTypedIR {
                 UnTypedIR::While(Box::new(cond.reconstruct_const_string()),
                 body.into_iter().map(|i| i.reconstruct_const_string()).collect(),
                 conts.into_iter().map(|i| i.reconstruct_const_string()).collect()),
+            UnTypedIR::Spanned(span, ir) => UnTypedIR::Spanned(span, Box::new(ir.reconstruct_const_string())),
             x => x
         }
     }
 }
 
+/// The decompiled subroutines of one class file.
+#[derive(Debug, Clone)]
+pub struct UnTypedClass {
+    pub name: String,
+    /// How many fields each object of the class has, as far as its
+    /// constructors and methods tell.
+    pub fields: usize,
+    pub subroutines: Vec<UnTypedIR>,
+}
+
+/// How a subroutine sets `pointer 0` in its first statement.
+enum Prologue {
+    /// `let POINTER_0 = ARG_0;`: the object is passed as argument 0.
+    Method,
+    /// `let POINTER_0 = Memory.alloc(n);`: a new object with `n` fields.
+    Constructor(usize),
+}
+
+fn prologue(stmt: &UnTypedIR) -> Option<Prologue> {
+    match stmt.unspanned() {
+        UnTypedIR::Assign(target, value) if **target == UnTypedIR::Var("POINTER_0".into()) => {
+            match value.unspanned() {
+                UnTypedIR::Var(v) if v == "ARG_0" => Some(Prologue::Method),
+                UnTypedIR::Call(func, args) if func == "Memory.alloc" && args.len() == 1 => match args[0] {
+                    UnTypedIR::ConstInt(n) if n >= 0 => Some(Prologue::Constructor(n as usize)),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The field a `this` segment variable such as `THIS_3` stands for.
+fn field_index(var: &str) -> Option<usize> {
+    var.strip_prefix("THIS_").and_then(|i| i.parse().ok())
+}
+
+impl UnTypedClass {
+    pub fn reconstruct_const_string(self) -> Self {
+        UnTypedClass {
+            name: self.name,
+            fields: self.fields,
+            subroutines: self.subroutines.into_iter().map(|s| s.reconstruct_const_string()).collect(),
+        }
+    }
+
+    /// Turns the calls of `Math.multiply` and `Math.divide` that Jack
+    /// compiles `*` and `/` into back into the operators.
+    pub fn recover_operators(self) -> Self {
+        let operator = |e: UnTypedIR| match e {
+            UnTypedIR::Call(func, mut args) if args.len() == 2 && (func == "Math.multiply" || func == "Math.divide") => {
+                let op = if func == "Math.multiply" { "*" } else { "/" };
+                let e2 = args.pop().unwrap();
+                let e1 = args.pop().unwrap();
+                UnTypedIR::Binary(op.into(), Box::new(e1), Box::new(e2))
+            }
+            // `do` takes nothing but a call, so a discarded product stays one.
+            UnTypedIR::Do(call) => UnTypedIR::Do(Box::new(match *call {
+                UnTypedIR::Binary(op, e1, e2) if op == "*" || op == "/" => {
+                    let func = if op == "*" { "Math.multiply" } else { "Math.divide" };
+                    UnTypedIR::Call(func.into(), vec![*e1, *e2])
+                }
+                call => call,
+            })),
+            e => e,
+        };
+        UnTypedClass {
+            subroutines: self.subroutines.into_iter().map(|s| s.transform(&operator)).collect(),
+            ..self
+        }
+    }
+
+    /// Turns methods and constructors into code on objects. A subroutine
+    /// is a method if it starts by pointing `pointer 0` at argument 0, and
+    /// a constructor if it points it at a new `Memory.alloc` block and
+    /// returns it. Their prologue is dropped, `POINTER_0` becomes `this`,
+    /// and `THIS_n` becomes the field `field_n`. The class gets as many
+    /// fields as its largest `Memory.alloc` asks for, or more if a method
+    /// uses a higher field.
+    pub fn reconstruct_fields(self) -> Self {
+        let mut fields = self.fields;
+        let subroutines = self.subroutines.into_iter().map(|s| match s {
+            UnTypedIR::FuncDef(mut sig, mut body) => {
+                let returns_this = body.iter().any(|stmt| returns(stmt, &UnTypedIR::Var("POINTER_0".into())));
+                match body.first().and_then(prologue) {
+                    Some(Prologue::Constructor(n)) if returns_this => {
+                        sig.kind = SubroutineKind::Constructor;
+                        fields = fields.max(n);
+                    }
+                    Some(Prologue::Method) => sig.kind = SubroutineKind::Method,
+                    _ => return UnTypedIR::FuncDef(sig, body),
+                }
+                body.remove(0);
+                for stmt in body.iter() {
+                    stmt.visit_vars(&mut |v| if let Some(i) = field_index(v) {
+                        fields = fields.max(i + 1);
+                    });
+                }
+                UnTypedIR::FuncDef(sig, body).map_vars(&|v| match v {
+                    "POINTER_0" => Some(UnTypedIR::Var("this".into())),
+                    v => field_index(v).map(|i| UnTypedIR::Var(format!("field_{}", i))),
+                })
+            }
+            s => s,
+        }).collect();
+        UnTypedClass { name: self.name, fields, subroutines }
+    }
+}
+
+/// Whether `stmt`, or a statement nested in it, returns `value`.
+fn returns(stmt: &UnTypedIR, value: &UnTypedIR) -> bool {
+    let any = |irs: &[UnTypedIR]| irs.iter().any(|s| returns(s, value));
+    match stmt {
+        UnTypedIR::Return(e) => e.unspanned() == value,
+        UnTypedIR::If(_, ts, fs, conts) => any(ts) || any(fs) || any(conts),
+        UnTypedIR::While(_, body, conts) => any(body) || any(conts),
+        UnTypedIR::Spanned(_, s) => returns(s, value),
+        _ => false,
+    }
+}
+
+/// Reconstructs objects across the classes of a program: first fields and
+/// subroutine kinds in every class, as `UnTypedClass::reconstruct_fields`
+/// does, then calls of the program's methods, which pass the object as
+/// their first argument and become `obj.method(args)`.
+pub fn reconstruct_objects(classes: Vec<UnTypedClass>) -> Vec<UnTypedClass> {
+    let classes: Vec<UnTypedClass> = classes.into_iter().map(UnTypedClass::reconstruct_fields).collect();
+    let defined: HashMap<String, SubroutineKind> = classes.iter()
+        .flat_map(|c| c.subroutines.iter())
+        .filter_map(|s| match s {
+            UnTypedIR::FuncDef(sig, _) => Some((sig.name.clone(), sig.kind)),
+            _ => None,
+        })
+        .collect();
+    // The program's own subroutines come first, should it define an OS
+    // class itself.
+    let is_method = |func: &str| match defined.get(func) {
+        Some(&kind) => kind == SubroutineKind::Method,
+        None => os_api::lookup(func).is_some_and(|os| os.kind == SubroutineKind::Method),
+    };
+    let method_call = |e: UnTypedIR| match e {
+        UnTypedIR::Call(func, mut args) if !args.is_empty() && is_method(&func) => {
+            let obj = args.remove(0);
+            UnTypedIR::MethodCall(Box::new(obj), func, args)
+        }
+        e => e,
+    };
+    classes.into_iter().map(|c| UnTypedClass {
+        subroutines: c.subroutines.into_iter().map(|s| s.transform(&method_call)).collect(),
+        ..c
+    }).collect()
+}
+
+/// Adds to `used` every subroutine `ir` calls for its result, that is
+/// other than as the call of a `do`.
+fn used_results<'a>(ir: &'a UnTypedIR, used: &mut HashSet<&'a str>) {
+    match ir {
+        UnTypedIR::Do(call) => {
+            for c in call.unspanned().children() {
+                used_results(c, used);
+            }
+            return;
+        }
+        UnTypedIR::Call(func, _) | UnTypedIR::MethodCall(_, func, _) => {
+            used.insert(func);
+        }
+        _ => {}
+    }
+    for c in ir.children() {
+        used_results(c, used);
+    }
+}
+
+fn returns_value(ir: &UnTypedIR) -> bool {
+    matches!(ir, UnTypedIR::Return(_)) || ir.children().into_iter().any(returns_value)
+}
+
+/// Decides which subroutines of the program are void. The lifter reads
+/// every `return 0` as `return;`; a subroutine is void if it never returns
+/// anything else and no call in the program uses its result. Everywhere
+/// else `return;` becomes `return(0);` again.
+///
+/// Calls of OS subroutines the program does not define itself are void
+/// if the OS table says so. Storing one in `temp 0` becomes `do`, even
+/// where `temp 0` is read back: there is no value to read.
+pub fn resolve_void_returns(classes: Vec<UnTypedClass>) -> Vec<UnTypedClass> {
+    let defined: HashSet<String> = classes.iter()
+        .flat_map(|c| c.subroutines.iter())
+        .filter_map(|s| match s {
+            UnTypedIR::FuncDef(sig, _) => Some(sig.name.clone()),
+            _ => None,
+        })
+        .collect();
+    let os_void = |func: &str| !defined.contains(func) && os_api::lookup(func).is_some_and(|os| os.ret.is_none());
+    let discard = |e: UnTypedIR| match e {
+        UnTypedIR::Assign(v, e) => match (*v, *e) {
+            (UnTypedIR::Var(ref t), UnTypedIR::Call(func, args)) if t == "TEMP_0" && os_void(&func) => {
+                UnTypedIR::Do(Box::new(UnTypedIR::Call(func, args)))
+            }
+            (v, e) => UnTypedIR::Assign(Box::new(v), Box::new(e)),
+        },
+        e => e,
+    };
+    let used: HashSet<String> = {
+        let mut used = HashSet::new();
+        for s in classes.iter().flat_map(|c| c.subroutines.iter()) {
+            used_results(s, &mut used);
+        }
+        used.into_iter().map(String::from).collect()
+    };
+    let zero = |e: UnTypedIR| match e {
+        UnTypedIR::ReturnVoid => UnTypedIR::Return(Box::new(UnTypedIR::ConstInt(0))),
+        e => e,
+    };
+    classes.into_iter().map(|c| UnTypedClass {
+        subroutines: c.subroutines.into_iter().map(|s| match s {
+            UnTypedIR::FuncDef(mut sig, body) => {
+                sig.void = !used.contains(&sig.name) && !body.iter().any(returns_value);
+                let f = UnTypedIR::FuncDef(sig.clone(), body).transform(&discard);
+                if sig.void { f } else { f.transform(&zero) }
+            }
+            s => s,
+        }).collect(),
+        ..c
+    }).collect()
+}
+
+impl Display for UnTypedClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "class {} {{", self.name)?;
+        if self.fields > 0 {
+            let fields: Vec<String> = (0..self.fields).map(|i| format!("field_{}", i)).collect();
+            writeln!(f, "field {};", fields.join(", "))?;
+        }
+        for s in self.subroutines.iter() {
+            write_stmt(f, s)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Writes one statement of a body on its own line. The alternate flag is
+/// passed on so `{:#}` prints span comments throughout nested bodies.
+fn write_stmt(f: &mut fmt::Formatter, stmt: &UnTypedIR) -> fmt::Result {
+    if f.alternate() {
+        writeln!(f, "{:#}", stmt)
+    } else {
+        writeln!(f, "{}", stmt)
+    }
+}
+
 impl Display for UnTypedIR {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &UnTypedIR::FuncDef(ref func, ref body) => {
-                write!(f, "function {}(...) {{\n", func)?;
+            UnTypedIR::FuncDef(sig, body) => {
+                writeln!(f, "{} {}({}) {{", sig.kind, sig.name, sig.declared_params().join(", "))?;
+                if sig.locals > 0 {
+                    let locals: Vec<String> = (0..sig.locals).map(|i| format!("LCL_{}", i)).collect();
+                    writeln!(f, "var {};", locals.join(", "))?;
+                }
                 for i in body.iter() {
-                    write!(f, "{}\n", i)?;
+                    write_stmt(f, i)?;
                 }
-                write!(f, "}}\n")
+                writeln!(f, "}}")
             } 
-            &UnTypedIR::ConstInt(i) => write!(f, "{}", i),
-            &UnTypedIR::ConstString(ref s) => write!(f, "\"{}\"", s),
-            &UnTypedIR::Var(ref s) => write!(f, "{}", s),
-            &UnTypedIR::Unary(ref op, ref e) => write!(f, "{}({})", op, e),
-            &UnTypedIR::Binary(ref op, ref e1, ref e2) => write!(f, "{} {} {}", e1, op, e2),
-            &UnTypedIR::Call(ref func, ref args) => {
+            UnTypedIR::ConstInt(i) => write!(f, "{}", i),
+            UnTypedIR::ConstString(s) => write!(f, "\"{}\"", s),
+            UnTypedIR::Var(s) => write!(f, "{}", s),
+            UnTypedIR::Unary(op, e) => write!(f, "{}({})", op, e),
+            UnTypedIR::Binary(op, e1, e2) => write!(f, "{} {} {}", e1, op, e2),
+            UnTypedIR::Call(func, args) => {
                 write!(f, "{}(", func)?;
-                for arg in args.iter().take(1) {
-                    write!(f, "{}", arg)?;
-                }
-                for arg in args.iter().skip(1) {
-                    write!(f, ", {}", arg)?;
+                write_args(f, args)
+            }
+            UnTypedIR::MethodCall(obj, func, args) => {
+                let method = func.rsplit('.').next().unwrap_or(func);
+                // Jack calls a method on the current object by its bare name.
+                match obj.unspanned() {
+                    UnTypedIR::Var(v) if v == "this" => write!(f, "{}(", method)?,
+                    obj => write!(f, "{}.{}(", obj, method)?,
                 }
-                write!(f, ")")
+                write_args(f, args)
             }
-            &UnTypedIR::Assign(ref e1, ref e2) => write!(f, "let {} = {};", e1, e2),
-            &UnTypedIR::Return(ref e) => write!(f, "return({});", e),
-            &UnTypedIR::If(ref e, ref taken, ref not_taken, ref cont) => {
-                write!(f, "if ({}) {{\n", e)?;
+            UnTypedIR::Assign(e1, e2) => write!(f, "let {} = {};", e1, e2),
+            UnTypedIR::Return(e) => write!(f, "return({});", e),
+            UnTypedIR::ReturnVoid => write!(f, "return;"),
+            UnTypedIR::Do(e) => write!(f, "do {};", e),
+            UnTypedIR::If(e, taken, not_taken, cont) => {
+                writeln!(f, "if ({}) {{", e)?;
                 for s in taken.iter() {
-                    write!(f, "{}\n", s)?;
+                    write_stmt(f, s)?;
                 }
                 if !not_taken.is_empty() {
-                    write!(f, "}} else {{\n")?;
+                    writeln!(f, "}} else {{")?;
                     for s in not_taken.iter() {
-                        write!(f, "{}\n", s)?;
+                        write_stmt(f, s)?;
                     }
-                    write!(f, "}}\n")?;
+                    writeln!(f, "}}")?;
                 } else {
-                    write!(f, "}}\n")?;
+                    writeln!(f, "}}")?;
                 }
                 for s in cont.iter() {
-                    write!(f, "{}\n", s)?;
+                    write_stmt(f, s)?;
                 }
                 Ok(())
             }
-            &UnTypedIR::While(ref e, ref body, ref cont) => {
-                write!(f, "while ({}) {{\n", e)?;
+            UnTypedIR::While(e, body, cont) => {
+                writeln!(f, "while ({}) {{", e)?;
                 for s in body.iter() {
-                    write!(f, "{}\n", s)?;
+                    write_stmt(f, s)?;
                 }
-                write!(f, "}}\n")?;
+                writeln!(f, "}}")?;
                 for s in cont.iter() {
-                    write!(f, "{}\n", s)?;
+                    write_stmt(f, s)?;
                 }
                 Ok(())
             }
-            &UnTypedIR::ArrayOffset(ref e1, ref e2) =>  write!(f, "{}[{}]", e1, e2)
+            UnTypedIR::ArrayOffset(e1, e2) =>  write!(f, "{}[{}]", e1, e2),
+            UnTypedIR::Spanned(span, e) => {
+                if f.alternate() {
+                    writeln!(f, "// {}", span)?;
+                    write!(f, "{:#}", e)
+                } else {
+                    write!(f, "{}", e)
+                }
+            }
+        }
+    }
+}
+
+/// The character `i` stands for, if a Jack string constant can hold it:
+/// printable ASCII other than the double quote.
+fn string_char(i: i32) -> Option<char> {
+    match i {
+        32..=126 if i != 34 => Some(i as u8 as char),
+        _ => None,
+    }
+}
+
+/// Whether `cmds` read `temp 0` before writing it.
+fn temp_read_later(cmds: &[Spanned<VmCommand>]) -> bool {
+    for c in cmds {
+        match c.node {
+            VmCommand::Push(Segment::TEMP, 0) => return true,
+            VmCommand::Pop(Segment::TEMP, 0) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether `cmds` access `that` before pointing `pointer 1` elsewhere.
+fn that_accessed_later(cmds: &[Spanned<VmCommand>]) -> bool {
+    for c in cmds {
+        match c.node {
+            VmCommand::Push(Segment::THAT, _) | VmCommand::Pop(Segment::THAT, _) => return true,
+            VmCommand::Pop(Segment::POINTER, 1) => return false,
+            _ => {}
         }
     }
+    false
 }
 
-pub fn get_untyped_ir_from_vm_commands(cmds: &[VmCommand]) -> Vec<UnTypedIR> {
-    let mut stack = Vec::new();
+/// Writes `args` separated by commas, and the closing parenthesis.
+fn write_args(f: &mut fmt::Formatter, args: &[UnTypedIR]) -> fmt::Result {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", arg)?;
+    }
+    write!(f, ")")
+}
+
+/// Pops the spans of the `n` topmost stack entries and merges them with the
+/// span of the command consuming them.
+fn merge_spans(spans: &mut Vec<Span>, n: usize, span: &Span) -> Span {
+    let at = spans.len() - n;
+    spans.drain(at..).fold(span.clone(), |acc, s| acc.merge(&s))
+}
+
+/// Names the variable behind `segment index`. Statics are private to their
+/// class file, so they are qualified with `class`.
+fn var_name(seg: Segment, i: i32, class: &str) -> String {
+    if seg == Segment::STATIC {
+        format!("{}.{:?}_{}", class, seg, i)
+    } else {
+        format!("{:?}_{}", seg, i)
+    }
+}
+
+/// The synthetic variable holding stack slot `i` where a value stays on
+/// the stack from one block into the next.
+pub fn stack_var(i: usize) -> UnTypedIR {
+    UnTypedIR::Var(format!("STACK_{}", i))
+}
+
+/// Whether the value of `e` may change when `var` is assigned. Reads of the
+/// `that` and `this` segments depend on the pointer that selects them.
+fn depends_on(e: &UnTypedIR, var: &str) -> bool {
+    match var {
+        "POINTER_0" => e.uses_any(&|v| v == var || v.starts_with("THIS_")),
+        "POINTER_1" => e.uses_any(&|v| v == var || v.starts_with("THAT_")),
+        _ => e.has_use(var),
+    }
+}
+
+/// The element `that i` names while `pointer 1` holds `address`.
+fn element(address: &UnTypedIR, i: i32) -> UnTypedIR {
+    match address.clone().to_array_offset() {
+        UnTypedIR::ArrayOffset(base, offset) if i != 0 => {
+            UnTypedIR::ArrayOffset(base, Box::new(UnTypedIR::Binary("+".into(), offset, Box::new(UnTypedIR::ConstInt(i)))))
+        }
+        e => e,
+    }
+}
+
+fn assign_pointer(address: UnTypedIR, span: Span) -> UnTypedIR {
+    let target = UnTypedIR::Var("POINTER_1".into());
+    UnTypedIR::Spanned(span, Box::new(UnTypedIR::Assign(Box::new(target), Box::new(address))))
+}
+
+/// Assigns every pending stack value to its `STACK_i` variable, bottom
+/// first, and leaves the variables on the stack in their place. Values
+/// already in their variable are left alone. Going bottom up is safe
+/// because values only ever move down the stack, so slot `i` can only hold
+/// the old contents of slots `i` and above.
+fn spill(stack: &mut [UnTypedIR], spans: &[Span], result: &mut Vec<UnTypedIR>) {
+    for (i, (e, span)) in stack.iter_mut().zip(spans).enumerate() {
+        let var = stack_var(i);
+        if *e != var {
+            let value = mem::replace(e, var.clone());
+            result.push(UnTypedIR::Spanned(span.clone(), Box::new(UnTypedIR::Assign(Box::new(var), Box::new(value)))));
+        }
+    }
+}
+
+/// Lifts one basic block to untyped IR by simulating the operand stack
+/// symbolically. The block starts with `entry_depth` values on the stack,
+/// read from `STACK_i` variables, and hands `exit_depth` values on to its
+/// successors the same way, so values can flow across jumps. Whatever
+/// remains above them, such as the condition of a closing `if-goto`, ends
+/// the result as expression statements.
+pub fn get_untyped_ir_from_vm_commands(cmds: &[Spanned<VmCommand>], class: &str, entry_depth: usize, exit_depth: usize) -> Vec<UnTypedIR> {
+    let mut stack: Vec<UnTypedIR> = (0..entry_depth).map(stack_var).collect();
+    let mut spans: Vec<Span> = match cmds.first() {
+        Some(c) => vec![c.span.clone(); entry_depth],
+        None => Vec::new(),
+    };
     let mut result = Vec::new();
-    for cmd in cmds {
-        match cmd {
-            &VmCommand::Push(seg, i) => {
-                if seg == Segment::CONST {
-                    stack.push(UnTypedIR::ConstInt(i));
-                } else {
-                    stack.push(UnTypedIR::Var(format!("{:?}_{}", seg, i)));
+    // The address last popped into `pointer 1`, with its span and whether
+    // `that` has been accessed through it. Jack compilers set the pointer
+    // right before reading or writing one array element, so instead of
+    // assigning `POINTER_1` the access itself becomes `a[i]`.
+    let mut that: Option<(UnTypedIR, Span, bool)> = None;
+    for (k, cmd) in cmds.iter().enumerate() {
+        let span = &cmd.span;
+        match cmd.node {
+            VmCommand::Push(seg, i) => {
+                stack.push(match (seg, that.as_mut()) {
+                    (Segment::CONST, _) => UnTypedIR::ConstInt(i),
+                    (Segment::THAT, Some((address, _, used))) => {
+                        *used = true;
+                        element(address, i)
+                    }
+                    (Segment::POINTER, Some((address, _, _))) if i == 1 => address.clone(),
+                    _ => UnTypedIR::Var(var_name(seg, i, class)),
+                });
+                spans.push(span.clone());
+            }
+            VmCommand::Pop(Segment::POINTER, 1) => {
+                let e = stack.pop().unwrap();
+                let span = merge_spans(&mut spans, 1, span);
+                if stack.iter().any(|e| depends_on(e, "POINTER_1")) {
+                    spill(&mut stack, &spans, &mut result);
                 }
+                that = Some((e, span, false));
             }
-            &VmCommand::Pop(seg, i) => {
+            VmCommand::Pop(seg, i) => {
                 let e = stack.pop().unwrap();
-                if seg == Segment::CONST {
-                    result.push(UnTypedIR::Assign(Box::new(UnTypedIR::Var(format!("{}", i))), Box::new(e)));
-                } else {
-                    result.push(UnTypedIR::Assign(Box::new(UnTypedIR::Var(format!("{:?}_{}", seg, i))), Box::new(e)));
+                let mut span = merge_spans(&mut spans, 1, span);
+                let target = match (seg, that.as_mut()) {
+                    (Segment::CONST, _) => UnTypedIR::Var(format!("{}", i)),
+                    (Segment::THAT, Some((address, address_span, used))) => {
+                        *used = true;
+                        span = span.merge(address_span);
+                        // Values still waiting on the stack must not see
+                        // the write.
+                        if stack.iter().any(UnTypedIR::reads_memory) {
+                            spill(&mut stack, &spans, &mut result);
+                        }
+                        element(address, i)
+                    }
+                    _ => UnTypedIR::Var(var_name(seg, i, class)),
+                };
+                if let UnTypedIR::Var(ref var) = target {
+                    if stack.iter().any(|e| depends_on(e, var)) {
+                        spill(&mut stack, &spans, &mut result);
+                    }
+                    // The pointer can no longer wait if its address is
+                    // about to change. It needs no assignment if the access
+                    // it was set for is done and no other one follows.
+                    if that.as_ref().is_some_and(|(address, _, _)| depends_on(address, var)) {
+                        let (address, span, used) = that.take().unwrap();
+                        if !used || that_accessed_later(&cmds[k + 1..]) {
+                            result.push(assign_pointer(address, span));
+                        }
+                    }
                 }
+                // `do f()` compiles to the call and `pop temp 0`. Whether
+                // the callee is void is not settled yet, and the program
+                // may define an OS class itself, so only reading `temp 0`
+                // back keeps the value for now; see `resolve_void_returns`.
+                let discarded = seg == Segment::TEMP && i == 0 &&
+                    matches!(e, UnTypedIR::Call(..)) && !temp_read_later(&cmds[k + 1..]);
+                let stmt = if discarded {
+                    UnTypedIR::Do(Box::new(e))
+                } else {
+                    UnTypedIR::Assign(Box::new(target), Box::new(e))
+                };
+                result.push(UnTypedIR::Spanned(span, Box::new(stmt)));
             }
-            &VmCommand::Call(ref func, n) => {
+            VmCommand::Call(ref func, n) => {
                 let mut args = Vec::new();
                 for _ in 0..n {
                     let e = stack.pop().unwrap();
                     args.push(e);
                 }
                 args.reverse();
-                // ToDO: we need to reverse this.
                 stack.push(UnTypedIR::Call(func.clone(), args));
+                let merged = merge_spans(&mut spans, n as usize, span);
+                spans.push(merged);
             }
-            &VmCommand::Neg => {
+            VmCommand::Neg => {
                 let e = stack.pop().unwrap();
                 stack.push(UnTypedIR::Unary("-".into(), Box::new(e)));
+                let merged = merge_spans(&mut spans, 1, span);
+                spans.push(merged);
             }
-            &VmCommand::Not => {
+            VmCommand::Not => {
                 let e = stack.pop().unwrap();
                 stack.push(UnTypedIR::Unary("~".into(), Box::new(e)));
+                let merged = merge_spans(&mut spans, 1, span);
+                spans.push(merged);
             }
-            &VmCommand::Add => {
-                let e1 = stack.pop().unwrap();
-                let e2 = stack.pop().unwrap();
-                stack.push(UnTypedIR::Binary("+".into(), Box::new(e1), Box::new(e2)));
-            }
-            &VmCommand::Lt => {
-                let e1 = stack.pop().unwrap();
-                let e2 = stack.pop().unwrap();
-                stack.push(UnTypedIR::Binary(">".into(), Box::new(e1), Box::new(e2)));
-            }
-            &VmCommand::Gt => {
-                let e1 = stack.pop().unwrap();
-                let e2 = stack.pop().unwrap();
-                stack.push(UnTypedIR::Binary("<".into(), Box::new(e1), Box::new(e2)));
+            VmCommand::Add | VmCommand::Sub | VmCommand::Lt | VmCommand::Gt |
+            VmCommand::Eq | VmCommand::And | VmCommand::Or => {
+                let op = match cmd.node {
+                    VmCommand::Add => "+",
+                    VmCommand::Sub => "-",
+                    VmCommand::Lt => "<",
+                    VmCommand::Gt => ">",
+                    VmCommand::Eq => "=",
+                    VmCommand::And => "&",
+                    _ => "|",
+                };
+                // The right operand is pushed last, so it is on top.
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
+                stack.push(UnTypedIR::Binary(op.into(), Box::new(left), Box::new(right)));
+                let merged = merge_spans(&mut spans, 2, span);
+                spans.push(merged);
             }
-            &VmCommand::Sub => {
-                let e1 = stack.pop().unwrap();
-                let e2 = stack.pop().unwrap();
-                stack.push(UnTypedIR::Binary("-".into(), Box::new(e2), Box::new(e1)));
-            }
-            &VmCommand::Eq => {
-                let e1 = stack.pop().unwrap();
-                let e2 = stack.pop().unwrap();
-                stack.push(UnTypedIR::Binary("=".into(), Box::new(e1), Box::new(e2)));
-            }
-            &VmCommand::And => {
-                let e1 = stack.pop().unwrap();
-                let e2 = stack.pop().unwrap();
-                stack.push(UnTypedIR::Binary("&".into(), Box::new(e1), Box::new(e2)));
-            }
-            &VmCommand::Or => {
-                let e1 = stack.pop().unwrap();
-                let e2 = stack.pop().unwrap();
-                stack.push(UnTypedIR::Binary("|".into(), Box::new(e1), Box::new(e2)));
-            }
-            &VmCommand::Return => {
+            VmCommand::Return => {
                 let e = stack.pop().unwrap();
-                result.push(UnTypedIR::Return(Box::new(e)));
+                let merged = merge_spans(&mut spans, 1, span);
+                // `return;` compiles to `push constant 0; return`, which
+                // `resolve_void_returns` turns back into a value wherever
+                // the program needs one.
+                let ret = match e {
+                    UnTypedIR::ConstInt(0) => UnTypedIR::ReturnVoid,
+                    e => UnTypedIR::Return(Box::new(e)),
+                };
+                result.push(UnTypedIR::Spanned(merged, Box::new(ret)));
             }
-            &VmCommand::FunDef(_, _) => panic!("FunDef should not be handled here!"),
-            &VmCommand::IfGoto(_) => panic!("IfGoto should not be handled here!"),
-            &VmCommand::Label(_) => panic!("Label should not be handled here!"),
-            &VmCommand::Goto(_) => panic!("Goto should not be handled here!"),
+            VmCommand::FunDef(_, _) => panic!("FunDef should not be handled here!"),
+            VmCommand::IfGoto(_) => panic!("IfGoto should not be handled here!"),
+            VmCommand::Label(_) => panic!("Label should not be handled here!"),
+            VmCommand::Goto(_) => panic!("Goto should not be handled here!"),
         }
     }
-    if let Some(e) = stack.pop() {
-        result.push(e);
+    // A pointer the block set but never used may be meant for a later one.
+    if let Some((address, span, false)) = that {
+        result.push(assign_pointer(address, span));
+    }
+    let rest = stack.split_off(exit_depth.min(stack.len()));
+    let rest_spans = spans.split_off(exit_depth.min(spans.len()));
+    spill(&mut stack, &spans, &mut result);
+    for (e, span) in rest.into_iter().zip(rest_spans) {
+        result.push(UnTypedIR::Spanned(span, Box::new(e)));
     }
     result
 }
\ No newline at end of file
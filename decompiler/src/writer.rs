@@ -0,0 +1,39 @@
+use std::io;
+use std::io::Write;
+
+use parser::{VmCommand, VmFile};
+
+/// Writes `cmds` as canonical VM source, one command per line.
+pub fn write_commands<W: Write>(w: &mut W, cmds: &[VmCommand]) -> io::Result<()> {
+    for c in cmds {
+        writeln!(w, "{}", c)?;
+    }
+    Ok(())
+}
+
+/// Writes `file` back as VM source with every command in canonical form.
+///
+/// With `keep_trivia` the comments, blank lines, whitespace and line endings
+/// of the original are kept as well. A file whose commands were already
+/// canonical is then reproduced byte for byte. Without it only the commands
+/// are written, as `write_commands` would.
+pub fn write_file<W: Write>(w: &mut W, file: &VmFile, keep_trivia: bool) -> io::Result<()> {
+    if !keep_trivia {
+        for c in file.lines.iter().filter_map(|l| l.command.as_ref()) {
+            writeln!(w, "{}", c.node)?;
+        }
+        return Ok(());
+    }
+    for line in file.lines.iter() {
+        w.write_all(line.indent.as_bytes())?;
+        if let Some(ref c) = line.command {
+            write!(w, "{}", c.node)?;
+        }
+        w.write_all(line.gap.as_bytes())?;
+        if let Some(ref comment) = line.comment {
+            write!(w, "//{}", comment)?;
+        }
+        w.write_all(line.newline.as_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::annotations::{Annotations, Target};
+use decompiler::types::Type;
+
+use common::jack;
+
+const MAIN: &str = "\
+function Main.main 2
+push constant 5
+call Array.new 1
+pop local 0
+push local 1
+call Output.printInt 1
+pop temp 0
+push constant 0
+return
+";
+
+#[test]
+fn keys_values_comments_and_prefixes() {
+    let annotations = Annotations::parse("types.toml", "\
+# hand-checked
+Main.main.local.1 = \"char\"
+
+[Ball]
+field.0 = \"Ball\"   # the next ball
+move.argument.1.name = \"dx\"
+bounce.return = \"Ball\"
+").unwrap();
+    assert_eq!(annotations.types.len(), 3);
+    assert_eq!(annotations.types[&Target::Local("Main.main".into(), 1)].0, Type::Char);
+    assert_eq!(annotations.types[&Target::Field("Ball".into(), 0)].0, Type::Class("Ball".into()));
+    assert_eq!(annotations.names[&Target::Argument("Ball.move".into(), 1)].0, "dx");
+    assert_eq!(annotations.types[&Target::Result("Ball.bounce".into())].0, Type::Class("Ball".into()));
+}
+
+#[test]
+fn malformed_lines_are_errors() {
+    let errors = Annotations::parse("types.toml", "\
+Main.main.local.0 = Array
+Main.main.local = \"int\"
+Main.main.local.0.name = \"while\"
+Main.main.local.0
+Main.main.return.name = \"result\"
+").err().unwrap();
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, [
+        "types.toml:1: error: the value of `Main.main.local.0` must be a quoted string",
+        "types.toml:2: error: `Main.main.local` does not name a local, argument, field, static or result",
+        "types.toml:3: error: `while` is not a Jack identifier",
+        "types.toml:4: error: expected `key = \"value\"`, found `Main.main.local.0`",
+        "types.toml:5: error: `Main.main.return` is not a variable and cannot be named",
+    ]);
+}
+
+#[test]
+fn annotations_name_variables_and_settle_their_types() {
+    let (text, diags) = jack("Main", MAIN, "\
+Main.main.local.0.name = \"scores\"
+Main.main.local.1 = \"char\"
+Main.main.local.1.name = \"key\"
+");
+    assert!(diags.is_empty(), "{:?}", diags);
+    assert!(text.contains("var Array scores;\n        var char key;\n"), "{}", text);
+    assert!(text.contains("let scores = Array.new(5);"), "{}", text);
+    assert!(text.contains("do Output.printInt(key);"), "{}", text);
+}
+
+#[test]
+fn contradicted_annotations_win_with_a_warning() {
+    let (text, diags) = jack("Main", MAIN, "\
+Main.main.local.0 = \"String\"
+Main.main.local.7 = \"int\"
+");
+    assert!(text.contains("var String local_0;"), "{}", text);
+    assert_eq!(diags, [
+        "types.toml:2: warning: `Main.main.local.7` is not in the program",
+        "Main.vm:2-4: warning: `Main.main.local.0` is annotated String but is used as Array here",
+    ]);
+}
+
+#[test]
+fn names_clashing_with_generated_or_class_names_are_refused() {
+    let source = "\
+function Main.main 2
+push static 0
+pop local 0
+push local 0
+pop local 1
+push constant 0
+return
+";
+    let (text, diags) = jack("Main", source, "\
+Main.static.0.name = \"count\"
+Main.main.local.0.name = \"local_1\"
+Main.main.local.1.name = \"count\"
+");
+    assert!(text.contains("static int count;"), "{}", text);
+    assert!(text.contains("let local_0 = count;\n        let local_1 = local_0;"), "{}", text);
+    assert!(diags.contains(&"types.toml:2: warning: `local_1` already names another variable; `Main.main.local.0` keeps its own name".to_string()), "{:?}", diags);
+    assert!(diags.contains(&"types.toml:3: warning: `count` already names another variable; `Main.main.local.1` keeps its own name".to_string()), "{:?}", diags);
+}
+
+#[test]
+fn annotated_class_must_exist() {
+    let (text, diags) = jack("Main", MAIN, "\
+Main.main.local.1 = \"Ball\"
+Main.main.local.0 = \"Output\"
+");
+    assert!(text.contains("var int local_1;"), "{}", text);
+    assert_eq!(diags[0], "types.toml:1: warning: `Ball` is not a class of the program or the OS; `Main.main.local.1` is typed by the evidence");
+    assert!(!diags.iter().any(|d| d.contains("`Output` is not a class")), "{:?}", diags);
+}
+
+#[test]
+fn annotated_results_type_what_their_subroutines_return() {
+    let source = "\
+function Main.id 0
+push argument 0
+return
+function Main.main 0
+push constant 0
+return
+";
+    let (text, diags) = jack("Main", source, "\
+[Main]
+id.return = \"String\"
+main.return = \"int\"
+f.return = \"int\"
+");
+    assert!(text.contains("function String id(String arg_0) {"), "{}", text);
+    assert_eq!(diags, [
+        "types.toml:3: warning: Main.main returns nothing; `Main.main.return` is ignored",
+        "types.toml:4: warning: `Main.f.return` is not in the program",
+    ]);
+}
@@ -0,0 +1,147 @@
+extern crate decompiler;
+
+mod common;
+
+use common::ir;
+
+#[test]
+fn screen_init_reads_and_writes_array_elements() {
+    let text = ir("Screen", include_str!("../test.vm"));
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Screen {",
+        "function Screen.init() {",
+        "var LCL_0;",
+        "let Screen.STATIC_1 = 16384;",
+        "let Screen.STATIC_2 = ~(0);",
+        "let Screen.STATIC_0 = Array.new(17);",
+        "let Screen.STATIC_0[0] = 1;",
+        "while (LCL_0 < 16) {",
+        "let LCL_0 = LCL_0 + 1;",
+        "let Screen.STATIC_0[LCL_0] = Screen.STATIC_0[LCL_0 - 1] + Screen.STATIC_0[LCL_0 - 1];",
+        "}",
+        "return;",
+        "}",
+        "}",
+    ]);
+    assert!(!text.contains("POINTER_1") && !text.contains("THAT_0") && !text.contains("TEMP_0"));
+}
+
+#[test]
+fn nested_element_is_read_and_written() {
+    let text = ir("Main", "\
+function Main.get 0
+push argument 2
+push argument 1
+add
+pop pointer 1
+push that 0
+push argument 0
+add
+pop pointer 1
+push that 0
+return
+function Main.set 0
+push argument 2
+push argument 1
+add
+pop pointer 1
+push that 0
+push argument 0
+add
+push argument 3
+pop temp 0
+pop pointer 1
+push temp 0
+pop that 0
+push constant 0
+return
+");
+    assert!(text.contains("return(ARG_0[ARG_1[ARG_2]]);"), "{}", text);
+    assert!(text.contains("let ARG_0[ARG_1[ARG_2]] = ARG_3;"), "{}", text);
+}
+
+#[test]
+fn pointer_is_kept_when_its_address_changes_before_use() {
+    let text = ir("Main", "\
+function Main.f 1
+push local 0
+pop pointer 1
+push constant 1
+pop local 0
+push that 0
+return
+");
+    assert!(text.contains("let POINTER_1 = LCL_0;"), "{}", text);
+    assert!(text.contains("return(THAT_0);"), "{}", text);
+}
+
+#[test]
+fn used_pointer_is_only_kept_for_later_accesses() {
+    let text = ir("Main", "\
+function Main.f 1
+push local 0
+push argument 0
+add
+pop pointer 1
+push that 0
+pop local 0
+push that 0
+return
+");
+    assert!(text.contains("let POINTER_1 = "), "{}", text);
+    assert!(text.contains("return(THAT_0);"), "{}", text);
+
+    let text = ir("Main", "\
+function Main.g 2
+push local 1
+push local 0
+add
+pop pointer 1
+push that 0
+pop local 1
+push local 1
+return
+");
+    assert!(!text.contains("POINTER_1"), "{}", text);
+}
+
+#[test]
+fn base_pushed_first_is_still_the_base() {
+    let text = ir("Main", "\
+function Main.f 0
+push argument 0
+push argument 1
+push constant 1
+sub
+add
+pop pointer 1
+push that 0
+return
+");
+    assert!(text.contains("return(ARG_0[ARG_1 - 1]);"), "{}", text);
+}
+
+#[test]
+fn array_typed_variable_is_the_base_whatever_the_order() {
+    let source = "\
+function Main.f 2
+push constant 5
+call Array.new 1
+pop local 0
+push local 0
+push local 1
+add
+pop pointer 1
+push that 0
+return
+";
+    let (text, _) = common::jack("Main", source, "");
+    assert!(text.contains("var Array local_0;"), "{}", text);
+    assert!(text.contains("var int local_1;"), "{}", text);
+    assert!(text.contains("return local_0[local_1];"), "{}", text);
+
+    let official = source.replace("push local 0\npush local 1\nadd", "push local 1\npush local 0\nadd");
+    let (text, _) = common::jack("Main", &official, "");
+    assert!(text.contains("return local_0[local_1];"), "{}", text);
+}
@@ -0,0 +1,87 @@
+extern crate decompiler;
+
+use decompiler::decompiler::{CfgError, Edge, EdgeKind, Graph};
+use decompiler::parser::{parse, Spanned, VmCommand};
+
+fn build(source: &str) -> Result<Graph<Spanned<VmCommand>>, CfgError> {
+    Graph::build(parse("Main.vm", source).unwrap())
+}
+
+fn edge(to: usize, kind: EdgeKind) -> Edge {
+    Edge { to, kind }
+}
+
+#[test]
+fn jumps_resolve_labels_defined_after_them() {
+    let graph = build("\
+goto SKIP
+push constant 1
+pop local 0
+label SKIP
+push constant 0
+return
+").unwrap();
+    // The block between the `goto` and its label cannot be reached and is
+    // dropped, leaving the jump, its target and the exit.
+    assert_eq!(graph.node_count(), 3);
+    assert_eq!(graph.edges(0), [edge(1, EdgeKind::Goto)]);
+    assert_eq!(graph.label(1), Some("SKIP"));
+    assert_eq!(graph.commands(1).len(), 2);
+}
+
+#[test]
+fn labels_must_be_defined_once() {
+    match build("label A\npush constant 0\nlabel A\nreturn\n") {
+        Err(CfgError::DuplicateLabel(label, span)) => {
+            assert_eq!(label, "A");
+            assert_eq!((span.first_line, span.last_line), (3, 3));
+        }
+        other => panic!("expected a duplicate label, got {:?}", other.err()),
+    }
+    match build("push argument 0\nif-goto NOWHERE\npush constant 0\nreturn\n") {
+        Err(CfgError::UndefinedLabel(label, span)) => {
+            assert_eq!(label, "NOWHERE");
+            assert_eq!((span.first_line, span.last_line), (2, 2));
+        }
+        other => panic!("expected an undefined label, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn returns_lead_to_a_single_empty_exit() {
+    let graph = build("\
+push argument 0
+if-goto ELSE
+push constant 1
+return
+label ELSE
+push constant 2
+return
+").unwrap();
+    assert_eq!(graph.node_count(), 4);
+    assert_eq!(graph.exit(), 3);
+    assert!(graph.commands(3).is_empty());
+    assert!(graph.edges(3).is_empty());
+    assert_eq!(graph.pred(3), [1, 2]);
+}
+
+#[test]
+fn edges_say_how_control_leaves_a_block() {
+    let graph = build("\
+push argument 0
+if-goto T
+push constant 1
+pop local 0
+goto END
+label T
+push constant 2
+pop local 0
+label END
+push local 0
+return
+").unwrap();
+    assert_eq!(graph.edges(0), [edge(2, EdgeKind::CondTaken), edge(1, EdgeKind::CondNotTaken)]);
+    assert_eq!(graph.edges(1), [edge(3, EdgeKind::Goto)]);
+    assert_eq!(graph.edges(2), [edge(3, EdgeKind::FallThrough)]);
+    assert_eq!(graph.edges(3), [edge(graph.exit(), EdgeKind::Return)]);
+}
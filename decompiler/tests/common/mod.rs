@@ -0,0 +1,65 @@
+//! Fixtures shared by the integration tests. Every test crate uses only
+//! part of them.
+#![allow(dead_code)]
+
+use decompiler::annotations::Annotations;
+use decompiler::decompiler::{decompile, Options};
+use decompiler::diagnostic::Diagnostic;
+use decompiler::jack::class_to_jack;
+use decompiler::program::{ClassFile, Program};
+use decompiler::types::{self, DeclaredClass};
+use decompiler::untyped_ir::UnTypedClass;
+
+use std::path::Path;
+
+/// A program of the single class file `name`.vm.
+pub fn program(name: &str, source: &str) -> Program {
+    let class = ClassFile::parse(Path::new(&format!("{}.vm", name)), source).unwrap();
+    Program { classes: vec![class] }
+}
+
+pub fn messages(diags: &[Diagnostic]) -> Vec<String> {
+    diags.iter().map(|d| d.to_string()).collect()
+}
+
+/// Decompiles `source` as the class file `name`.vm, as the binary does
+/// for the ir and json formats.
+pub fn untyped(name: &str, source: &str, options: Options) -> (Vec<UnTypedClass>, Vec<String>) {
+    let mut diags = Vec::new();
+    let classes = decompile(&program(name, source), options, &mut diags);
+    (classes, messages(&diags))
+}
+
+/// The IR `source` decompiles to, for a program that decompiles cleanly.
+pub fn ir(name: &str, source: &str) -> String {
+    let (classes, diags) = untyped(name, source, Options::default());
+    assert!(diags.is_empty(), "{:?}", diags);
+    classes[0].to_string()
+}
+
+/// Decompiles `source` as the class file `name`.vm and infers its types,
+/// with `annotations` read as the annotation file types.toml.
+pub fn typed(name: &str, source: &str, annotations: &str, options: Options) -> (Vec<DeclaredClass>, Vec<String>) {
+    let annotations = Annotations::parse("types.toml", annotations).unwrap();
+    let mut diags = Vec::new();
+    let classes = decompile(&program(name, source), options, &mut diags);
+    let classes = types::infer(classes, &annotations, &mut diags);
+    (classes, messages(&diags))
+}
+
+/// The Jack class `source` decompiles to, as the binary prints it.
+pub fn jack(name: &str, source: &str, annotations: &str) -> (String, Vec<String>) {
+    let (classes, diags) = typed(name, source, annotations, Options::default());
+    (class_to_jack(&classes[0], false), diags)
+}
+
+/// The predecessor lists of the graph with successor lists `succs`.
+pub fn preds(succs: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut preds = vec![Vec::new(); succs.len()];
+    for (n, ss) in succs.iter().enumerate() {
+        for &s in ss.iter() {
+            preds[s].push(n);
+        }
+    }
+    preds
+}
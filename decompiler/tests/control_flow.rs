@@ -0,0 +1,189 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::decompiler::Options;
+
+use common::untyped;
+
+#[test]
+fn functions_that_cannot_be_decompiled_are_left_out() {
+    let (classes, diags) = untyped("R", "\
+function R.good 0
+push constant 1
+return
+function R.bad 0
+push constant 1
+push constant 2
+return
+", Options::default());
+    assert_eq!(classes[0].to_string(), "\
+class R {
+function R.good() {
+return(1);
+}
+
+}
+");
+    assert_eq!(diags, [
+        "R.vm:7: error: `return` leaves 1 value on the stack",
+        "R.vm:4: error: R.bad is left out: its operand stack is not balanced",
+    ]);
+}
+
+/// A loop between `A` and `B` that the entry jumps into at both labels.
+const IRREDUCIBLE: &str = "\
+function Main.f 1
+push argument 0
+if-goto B
+label A
+push local 0
+push constant 1
+add
+pop local 0
+label B
+push local 0
+push constant 10
+lt
+if-goto A
+push local 0
+return
+";
+
+#[test]
+fn irreducible_loop_becomes_a_state_machine() {
+    let (classes, diags) = untyped("Main", IRREDUCIBLE, Options::default());
+    assert_eq!(diags, [
+        "Main.vm:1: warning: Main.f: jumps enter a loop other than at its start, between `A`, `B`; \
+         decompiled as a state machine",
+    ]);
+    let text = classes[0].to_string();
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Main {",
+        "function Main.f(ARG_0) {",
+        "var LCL_0;",
+        "let STATE = 0;",
+        "while (~(STATE = 4)) {",
+        "if (STATE = 0) {",
+        "if (ARG_0) {",
+        "let STATE = 2;",
+        "} else {",
+        "let STATE = 1;",
+        "}",
+        "}",
+        "if (STATE = 1) {",
+        "let LCL_0 = LCL_0 + 1;",
+        "let STATE = 2;",
+        "}",
+        "if (STATE = 2) {",
+        "if (LCL_0 < 10) {",
+        "let STATE = 1;",
+        "} else {",
+        "let STATE = 3;",
+        "}",
+        "}",
+        "if (STATE = 3) {",
+        "return(LCL_0);",
+        "}",
+        "}",
+        "}",
+        "}",
+    ]);
+}
+
+#[test]
+fn state_machine_stops_when_control_runs_off_the_end() {
+    let source = IRREDUCIBLE.replace("push local 0\nreturn\n", "push constant 0\npop local 0\n");
+    let (classes, _) = untyped("Main", &source, Options::default());
+    let text = classes[0].to_string();
+    assert!(text.contains("if (STATE = 3) {\nlet LCL_0 = 0;\nlet STATE = 4;\n}"), "{}", text);
+}
+
+#[test]
+fn loop_tested_at_its_latch_leaves_through_a_negated_condition() {
+    let (classes, diags) = untyped("Main", "\
+function Main.f 1
+label LOOP
+push local 0
+push constant 1
+add
+pop local 0
+push local 0
+push constant 10
+lt
+if-goto LOOP
+push local 0
+return
+", Options::default());
+    assert!(diags.is_empty(), "{:?}", diags);
+    let text = classes[0].to_string();
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Main {",
+        "function Main.f() {",
+        "var LCL_0;",
+        "let LOOP_0 = ~(0);",
+        "while (LOOP_0) {",
+        "let LCL_0 = LCL_0 + 1;",
+        "if (~(LCL_0 < 10)) {",
+        "let LOOP_0 = 0;",
+        "}",
+        "}",
+        "return(LCL_0);",
+        "}",
+        "}",
+    ]);
+}
+
+/// `if (a_i) { if (a_i+1) { return; } }` `n` times, then a tail.
+fn nested_guards(n: usize) -> String {
+    let mut source = String::from("function Main.f 0\n");
+    for i in 0..n {
+        source += &format!("\
+push argument {i}
+if-goto T{i}
+goto E{i}
+label T{i}
+push argument {j}
+if-goto R{i}
+goto E{i}
+label R{i}
+push constant 0
+return
+label E{i}
+", i = i, j = i + 1);
+    }
+    source + "push constant 1\ncall Output.printInt 1\npop temp 0\npush constant 0\nreturn\n"
+}
+
+#[test]
+fn code_after_early_returns_is_emitted_once() {
+    let (classes, diags) = untyped("Main", &nested_guards(2), Options::default());
+    assert!(diags.is_empty(), "{:?}", diags);
+    let text = classes[0].to_string();
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Main {",
+        "function Main.f(ARG_0, ARG_1, ARG_2) {",
+        "if (ARG_0) {",
+        "if (ARG_1) {",
+        "return;",
+        "}",
+        "}",
+        "if (ARG_1) {",
+        "if (ARG_2) {",
+        "return;",
+        "}",
+        "}",
+        "do Output.printInt(1);",
+        "return;",
+        "}",
+        "}",
+    ]);
+
+    let (classes, _) = untyped("Main", &nested_guards(14), Options::default());
+    let text = classes[0].to_string();
+    assert_eq!(text.matches("Output.printInt").count(), 1);
+    assert!(text.lines().count() < 150, "{}", text);
+}
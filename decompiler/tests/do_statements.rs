@@ -0,0 +1,106 @@
+extern crate decompiler;
+
+mod common;
+
+use common::ir;
+
+#[test]
+fn discarded_calls_and_void_returns() {
+    let text = ir("Main", "\
+function Main.main 0
+call Main.draw 0
+pop temp 0
+call Main.size 0
+pop temp 0
+push constant 0
+return
+function Main.draw 0
+push constant 0
+return
+function Main.size 0
+push constant 0
+return
+function Main.twice 0
+call Main.size 0
+push constant 2
+call Math.multiply 2
+return
+");
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Main {",
+        "function Main.main() {",
+        "do Main.draw();",
+        "do Main.size();",
+        "return;",
+        "}",
+        "function Main.draw() {",
+        "return;",
+        "}",
+        // Main.twice uses the result of Main.size, so it returns a value.
+        "function Main.size() {",
+        "return(0);",
+        "}",
+        "function Main.twice() {",
+        "return(Main.size() * 2);",
+        "}",
+        "}",
+    ]);
+}
+
+#[test]
+fn call_stored_through_temp_stays_a_value() {
+    let text = ir("Main", "\
+function Main.main 1
+push constant 2
+push local 0
+add
+call Main.size 0
+pop temp 0
+pop pointer 1
+push temp 0
+pop that 0
+push constant 0
+return
+function Main.size 0
+push constant 7
+return
+");
+    assert!(text.contains("let LCL_0[2] = Main.size();"), "{}", text);
+    assert!(!text.contains("do "), "{}", text);
+}
+
+#[test]
+fn void_os_name_read_back_through_temp_stays_a_value() {
+    // The program defines its own Output.printInt, which returns a value.
+    let text = ir("Output", "\
+function Output.show 0
+push argument 0
+call Output.printInt 1
+pop temp 0
+push temp 0
+return
+function Output.printInt 0
+push argument 0
+return
+");
+    assert!(text.contains("let TEMP_0 = Output.printInt(ARG_0);"), "{}", text);
+    assert!(text.contains("return(TEMP_0);"), "{}", text);
+    assert!(!text.contains("do "), "{}", text);
+}
+
+#[test]
+fn void_os_call_read_back_through_temp_is_still_discarded() {
+    // Reading `temp 0` back would keep the call as a value, but the OS
+    // table says Output.printInt returns nothing.
+    let text = ir("Main", "\
+function Main.show 0
+push argument 0
+call Output.printInt 1
+pop temp 0
+push temp 0
+return
+");
+    assert!(text.contains("do Output.printInt(ARG_0);"), "{}", text);
+    assert!(!text.contains("let TEMP_0"), "{}", text);
+}
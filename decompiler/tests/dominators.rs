@@ -0,0 +1,122 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::decompiler::{function_graph, EdgeKind};
+use decompiler::dominators::DominatorTree;
+
+use common::{preds, program};
+
+fn tree(succs: &[Vec<usize>]) -> DominatorTree {
+    DominatorTree::new(succs, &preds(succs), 0)
+}
+
+fn sorted(nodes: &[usize]) -> Vec<usize> {
+    let mut nodes = nodes.to_vec();
+    nodes.sort();
+    nodes
+}
+
+#[test]
+fn diamond() {
+    let doms = tree(&[vec![1, 2], vec![3], vec![3], vec![]]);
+    let idoms: Vec<Option<usize>> = (0..4).map(|n| doms.idom(n)).collect();
+    assert_eq!(idoms, [None, Some(0), Some(0), Some(0)]);
+    assert_eq!(sorted(doms.children(0)), [1, 2, 3]);
+    assert!(doms.children(1).is_empty());
+
+    assert!(doms.dominates(0, 3) && doms.dominates(3, 3));
+    assert!(!doms.dominates(1, 3) && !doms.dominates(3, 0));
+
+    assert!(doms.frontier(0).is_empty());
+    assert_eq!(doms.frontier(1), [3]);
+    assert_eq!(doms.frontier(2), [3]);
+    assert!(doms.frontier(3).is_empty());
+}
+
+#[test]
+fn nested_loops() {
+    // 1 heads the outer loop, which leaves to 5; 2 heads the inner one.
+    // Nothing reaches 6.
+    let doms = tree(&[vec![1], vec![2, 5], vec![3, 4], vec![2], vec![1], vec![], vec![5]]);
+    let idoms: Vec<Option<usize>> = (0..7).map(|n| doms.idom(n)).collect();
+    assert_eq!(idoms, [None, Some(0), Some(1), Some(2), Some(2), Some(1), None]);
+    assert_eq!(sorted(doms.children(1)), [2, 5]);
+    assert_eq!(sorted(doms.children(2)), [3, 4]);
+    assert_eq!(doms.dominators(3), [3, 2, 1, 0]);
+
+    assert!(doms.dominates(1, 4) && doms.dominates(2, 3));
+    assert!(!doms.dominates(3, 4) && !doms.dominates(2, 5));
+    assert!(!doms.is_reachable(6) && !doms.dominates(0, 6));
+
+    assert!(doms.frontier(0).is_empty());
+    assert_eq!(sorted(doms.frontier(1)), [1]);
+    assert_eq!(sorted(doms.frontier(2)), [1, 2]);
+    assert_eq!(sorted(doms.frontier(3)), [2]);
+    assert_eq!(sorted(doms.frontier(4)), [1]);
+    assert!(doms.frontier(5).is_empty());
+}
+
+#[test]
+fn post_dominators_end_at_a_virtual_exit() {
+    let program = program("Main", "\
+function Main.f 0
+push argument 0
+if-goto LOOP
+push constant 0
+return
+label LOOP
+goto LOOP
+");
+    // Blocks: 0 branches, 1 returns, 2 loops forever, 3 is the exit.
+    let graph = function_graph(&program.classes[0].functions[0]).unwrap();
+    assert_eq!(graph.exit(), 3);
+    let pdoms = graph.post_dominator_tree();
+    let virtual_exit = graph.node_count();
+    assert_eq!(pdoms.root(), virtual_exit);
+    let ipdoms: Vec<Option<usize>> = (0..=virtual_exit).map(|n| pdoms.idom(n)).collect();
+    assert_eq!(ipdoms, [Some(virtual_exit), Some(3), Some(virtual_exit), Some(virtual_exit), None]);
+    assert!(pdoms.dominates(3, 1) && !pdoms.dominates(3, 0));
+}
+
+#[test]
+fn control_dependence_follows_the_post_dominance_frontier() {
+    let program = program("Main", "\
+function Main.f 1
+push argument 0
+if-goto T
+push constant 1
+pop local 0
+goto LOOP
+label T
+push constant 2
+pop local 0
+label LOOP
+push local 0
+push constant 10
+lt
+not
+if-goto DONE
+push local 0
+push constant 1
+add
+pop local 0
+goto LOOP
+label DONE
+push local 0
+return
+");
+    // Blocks: 0 branches to 2 (T) or 1; 3 (LOOP) tests and leaves to 5
+    // or runs the body 4; 6 is the exit.
+    let graph = function_graph(&program.classes[0].functions[0]).unwrap();
+    assert_eq!(graph.exit(), 6);
+    let cdg = graph.control_dependence();
+    assert!(cdg.dependences(0).is_empty());
+    assert_eq!(cdg.dependences(1), [(0, EdgeKind::CondNotTaken)]);
+    assert_eq!(cdg.dependences(2), [(0, EdgeKind::CondTaken)]);
+    assert_eq!(cdg.dependences(3), [(3, EdgeKind::CondNotTaken)]);
+    assert_eq!(cdg.dependences(4), [(3, EdgeKind::CondNotTaken)]);
+    assert!(cdg.dependences(5).is_empty());
+    assert_eq!(cdg.dependents(0).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(cdg.dependents(3).collect::<Vec<_>>(), [3, 4]);
+}
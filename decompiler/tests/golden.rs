@@ -0,0 +1,193 @@
+extern crate decompiler;
+
+mod common;
+
+// Each test is a Jack class and the VM code the course's Jack compiler
+// generates for it. Annotations give back the names compilation loses,
+// so the decompiled class should read exactly like the source.
+
+fn decompile(name: &str, vm: &str, names: &str) -> String {
+    let (text, diags) = common::jack(name, vm, names);
+    assert!(diags.is_empty(), "{:?}", diags);
+    text
+}
+
+#[test]
+fn loops_arrays_and_comparisons() {
+    let source = "\
+class Main {
+    function void main() {
+        var Array a;
+        var int i, sum;
+        let a = Array.new(3);
+        let i = 0;
+        let sum = 0;
+        while (i < 3) {
+            let a[i] = i * 2;
+            let sum = sum + a[i];
+            let i = i + 1;
+        }
+        if (sum > 5) {
+            do Output.printInt(sum - 5);
+        }
+        return;
+    }
+}
+";
+    let vm = "\
+function Main.main 3
+push constant 3
+call Array.new 1
+pop local 0
+push constant 0
+pop local 1
+push constant 0
+pop local 2
+label WHILE_EXP0
+push local 1
+push constant 3
+lt
+not
+if-goto WHILE_END0
+push local 1
+push local 0
+add
+push local 1
+push constant 2
+call Math.multiply 2
+pop temp 0
+pop pointer 1
+push temp 0
+pop that 0
+push local 2
+push local 1
+push local 0
+add
+pop pointer 1
+push that 0
+add
+pop local 2
+push local 1
+push constant 1
+add
+pop local 1
+goto WHILE_EXP0
+label WHILE_END0
+push local 2
+push constant 5
+gt
+if-goto IF_TRUE0
+goto IF_FALSE0
+label IF_TRUE0
+push local 2
+push constant 5
+sub
+call Output.printInt 1
+pop temp 0
+label IF_FALSE0
+push constant 0
+return
+";
+    assert_eq!(decompile("Main", vm, "\
+[Main.main]
+local.0.name = \"a\"
+local.1.name = \"i\"
+local.2.name = \"sum\"
+"), source);
+}
+
+#[test]
+fn calls_keep_the_order_they_run_in() {
+    let source = "\
+class Counter {
+    field int count;
+
+    constructor Counter new() {
+        let count = 0;
+        return this;
+    }
+
+    method int next() {
+        let count = count + 1;
+        return count;
+    }
+
+    method boolean before(Counter other) {
+        return next() < other.next();
+    }
+}
+";
+    let vm = "\
+function Counter.new 0
+push constant 1
+call Memory.alloc 1
+pop pointer 0
+push constant 0
+pop this 0
+push pointer 0
+return
+function Counter.next 0
+push argument 0
+pop pointer 0
+push this 0
+push constant 1
+add
+pop this 0
+push this 0
+return
+function Counter.before 0
+push argument 0
+pop pointer 0
+push pointer 0
+call Counter.next 1
+push argument 1
+call Counter.next 1
+lt
+return
+";
+    assert_eq!(decompile("Counter", vm, "\
+[Counter]
+field.0.name = \"count\"
+before.argument.1.name = \"other\"
+"), source);
+}
+
+#[test]
+fn logic_and_strings() {
+    let source = "\
+class Greeter {
+    function void greet(int n) {
+        if (~(n = 0) & (n < 10)) {
+            do Output.printString(\"Hi\");
+        }
+        return;
+    }
+}
+";
+    let vm = "\
+function Greeter.greet 0
+push argument 0
+push constant 0
+eq
+not
+push argument 0
+push constant 10
+lt
+and
+if-goto IF_TRUE0
+goto IF_FALSE0
+label IF_TRUE0
+push constant 2
+call String.new 1
+push constant 72
+call String.appendChar 2
+push constant 105
+call String.appendChar 2
+call Output.printString 1
+pop temp 0
+label IF_FALSE0
+push constant 0
+return
+";
+    assert_eq!(decompile("Greeter", vm, "Greeter.greet.argument.0.name = \"n\"\n"), source);
+}
@@ -0,0 +1,126 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::annotations::Annotations;
+use decompiler::jack::class_to_jack;
+use decompiler::types;
+use decompiler::untyped_ir::{Signature, UnTypedClass, UnTypedIR};
+
+/// Decompiles `source` as the class file `name`.vm into Jack, whatever
+/// types are left to guess.
+fn decompile(name: &str, source: &str) -> String {
+    common::jack(name, source, "").0
+}
+
+#[test]
+fn class_with_fields_constructor_and_methods() {
+    let text = decompile("Point", "\
+function Point.new 0
+push constant 2
+call Memory.alloc 1
+pop pointer 0
+push argument 0
+pop this 0
+push constant 0
+pop this 1
+push pointer 0
+return
+function Point.getX 0
+push argument 0
+pop pointer 0
+push this 0
+return
+function Point.dist 1
+push argument 0
+pop pointer 0
+push pointer 0
+call Point.getX 1
+push argument 1
+call Point.getX 1
+sub
+pop local 0
+push local 0
+push constant 0
+lt
+not
+if-goto END
+push local 0
+neg
+return
+label END
+push local 0
+return
+");
+    assert_eq!(text, "\
+class Point {
+    field int field_0, field_1;
+
+    constructor Point new(int arg_0) {
+        let field_0 = arg_0;
+        let field_1 = 0;
+        return this;
+    }
+
+    method int getX() {
+        return field_0;
+    }
+
+    method int dist(Point arg_1) {
+        var int local_0;
+        let local_0 = getX() - arg_1.getX();
+        if (local_0 < 0) {
+            return -local_0;
+        }
+        return local_0;
+    }
+}
+");
+}
+
+#[test]
+fn memory_jack_cannot_name_goes_through_peek_and_poke() {
+    let text = decompile("Main", "\
+function Main.f 0
+push that 0
+pop that 1
+push argument 0
+push constant 1
+add
+pop pointer 1
+push that 0
+return
+");
+    assert_eq!(text, "\
+class Main {
+    function int f(Array arg_0) {
+        do Memory.poke(Memory.peek(4) + 1, Memory.peek(Memory.peek(4)));
+        return arg_0[1];
+    }
+}
+");
+}
+
+#[test]
+fn value_computed_for_nothing_is_stored_in_a_temp() {
+    let read = UnTypedIR::ArrayOffset(Box::new(UnTypedIR::Var("ARG_0".into())), Box::new(UnTypedIR::ConstInt(2)));
+    let class = UnTypedClass {
+        name: "Main".into(),
+        fields: 0,
+        subroutines: vec![UnTypedIR::FuncDef(Signature::function("Main.f".into(), 1, 0), vec![
+            read,
+            UnTypedIR::Return(Box::new(UnTypedIR::ConstInt(0))),
+        ])],
+    };
+    let mut diags = Vec::new();
+    let classes = types::infer(vec![class], &Annotations::default(), &mut diags);
+    assert_eq!(class_to_jack(&classes[0], false), "\
+class Main {
+    function int f(Array arg_0) {
+        var int temp_0;
+        let temp_0 = arg_0[2];
+        return 0;
+    }
+}
+");
+}
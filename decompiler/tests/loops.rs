@@ -0,0 +1,48 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::dominators::DominatorTree;
+use decompiler::loops::LoopForest;
+
+use common::preds;
+
+fn forest(succs: &[Vec<usize>]) -> LoopForest {
+    let preds = preds(succs);
+    let doms = DominatorTree::new(succs, &preds, 0);
+    LoopForest::new(succs, &preds, &doms)
+}
+
+#[test]
+fn nested_loops_know_their_bodies_exits_and_parents() {
+    // 1 heads the outer loop, which leaves to 5; 2 heads the inner one.
+    let succs = vec![vec![1], vec![2, 5], vec![3, 4], vec![2], vec![1], vec![]];
+    let forest = forest(&succs);
+    assert_eq!(forest.loops().len(), 2);
+    assert!(forest.irreducible_edges().is_empty());
+
+    let outer = forest.loop_at(1).unwrap();
+    assert_eq!(outer.body, [1, 2, 3, 4]);
+    assert_eq!(outer.latches, [4]);
+    assert_eq!(outer.exits, [(1, 5)]);
+    assert_eq!(outer.parent, None);
+
+    let inner = forest.loop_at(2).unwrap();
+    assert_eq!(inner.body, [2, 3]);
+    assert_eq!(inner.latches, [3]);
+    assert_eq!(inner.exits, [(2, 4)]);
+    assert_eq!(inner.parent, Some(0));
+    assert!(outer.contains(3) && !inner.contains(4));
+
+    assert!(forest.is_header(1) && forest.is_header(2) && !forest.is_header(3));
+    let innermost: Vec<Option<usize>> = (0..6).map(|n| forest.innermost(n)).collect();
+    assert_eq!(innermost, [None, Some(0), Some(1), Some(1), Some(0), None]);
+}
+
+#[test]
+fn cycle_entered_at_two_nodes_is_no_loop() {
+    let succs = vec![vec![1, 2], vec![2, 3], vec![1], vec![]];
+    let forest = forest(&succs);
+    assert!(forest.loops().is_empty());
+    assert_eq!(forest.irreducible_edges(), [(2, 1)]);
+}
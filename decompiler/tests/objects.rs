@@ -0,0 +1,122 @@
+extern crate decompiler;
+
+mod common;
+
+use common::ir;
+
+#[test]
+fn fields_come_from_the_allocation_and_every_use() {
+    let text = ir("Point", "\
+function Point.new 0
+push constant 2
+call Memory.alloc 1
+pop pointer 0
+push argument 0
+pop this 0
+push pointer 0
+return
+function Point.setZ 0
+push argument 0
+pop pointer 0
+push argument 1
+pop this 2
+push constant 0
+return
+function Point.getY 0
+push argument 0
+pop pointer 0
+push this 1
+return
+");
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Point {",
+        "field field_0, field_1, field_2;",
+        "constructor Point.new(ARG_0) {",
+        "let field_0 = ARG_0;",
+        "return(this);",
+        "}",
+        "method Point.setZ(ARG_1) {",
+        "let field_2 = ARG_1;",
+        "return;",
+        "}",
+        "method Point.getY() {",
+        "return(field_1);",
+        "}",
+        "}",
+    ]);
+}
+
+#[test]
+fn functions_keep_their_this_segment() {
+    let text = ir("Main", "\
+function Main.f 0
+push argument 0
+push constant 1
+add
+pop pointer 0
+push this 0
+return
+");
+    assert!(text.contains("class Main {\nfunction Main.f(ARG_0) {"), "{}", text);
+    assert!(text.contains("THIS_0"), "{}", text);
+    assert!(!text.contains("field_") && !text.contains("this)"), "{}", text);
+}
+
+#[test]
+fn prologues_classify_subroutines_and_method_calls_take_their_object() {
+    let text = ir("Ball", "\
+function Ball.new 0
+push constant 1
+call Memory.alloc 1
+pop pointer 0
+push pointer 0
+return
+function Ball.move 0
+push argument 0
+pop pointer 0
+push pointer 0
+push argument 1
+call Ball.step 2
+pop temp 0
+push constant 0
+return
+function Ball.step 0
+push argument 0
+pop pointer 0
+push argument 1
+pop this 0
+push constant 0
+return
+function Ball.make 0
+call Ball.new 0
+push constant 3
+call Ball.step 2
+pop temp 0
+push constant 5
+call String.new 1
+call String.length 1
+return
+");
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Ball {",
+        "field field_0;",
+        "constructor Ball.new() {",
+        "return(this);",
+        "}",
+        "method Ball.move(ARG_1) {",
+        "do step(ARG_1);",
+        "return;",
+        "}",
+        "method Ball.step(ARG_1) {",
+        "let field_0 = ARG_1;",
+        "return;",
+        "}",
+        "function Ball.make() {",
+        "do Ball.new().step(3);",
+        "return(String.new(5).length());",
+        "}",
+        "}",
+    ]);
+}
@@ -0,0 +1,72 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::decompiler::Options;
+use decompiler::jack::class_to_jack;
+
+/// `(arg_0 - arg_1) * arg_2 / 2 - arg_0 * Math.multiply(arg_1, 3)`.
+const CALC: &str = "\
+function Calc.f 0
+push argument 0
+push argument 1
+sub
+push argument 2
+call Math.multiply 2
+push constant 2
+call Math.divide 2
+push argument 0
+push argument 1
+push constant 3
+call Math.multiply 2
+call Math.multiply 2
+sub
+return
+";
+
+fn decompile(math_calls: bool) -> String {
+    let (classes, diags) = common::typed("Calc", CALC, "", Options { math_calls });
+    assert!(diags.is_empty());
+    class_to_jack(&classes[0], false)
+}
+
+#[test]
+fn math_calls_become_operators_with_the_parentheses_they_need() {
+    assert_eq!(decompile(false), "\
+class Calc {
+    function int f(int arg_0, int arg_1, int arg_2) {
+        return (arg_0 - arg_1) * arg_2 / 2 - (arg_0 * (arg_1 * 3));
+    }
+}
+");
+}
+
+#[test]
+fn math_calls_can_be_kept() {
+    assert_eq!(decompile(true), "\
+class Calc {
+    function int f(int arg_0, int arg_1, int arg_2) {
+        return Math.divide(Math.multiply(arg_0 - arg_1, arg_2), 2) - Math.multiply(arg_0, Math.multiply(arg_1, 3));
+    }
+}
+");
+}
+
+#[test]
+fn discarded_math_calls_stay_calls() {
+    let (text, diags) = common::jack("Main", "\
+function Main.f 0
+push argument 0
+push argument 1
+call Math.multiply 2
+pop temp 0
+push argument 0
+push constant 2
+call Math.divide 2
+pop temp 0
+push constant 0
+return
+", "");
+    assert!(diags.is_empty(), "{:?}", diags);
+    assert!(text.contains("do Math.multiply(arg_0, arg_1);\n        do Math.divide(arg_0, 2);"), "{}", text);
+}
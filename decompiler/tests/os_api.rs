@@ -0,0 +1,75 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::os_api::{self, OS_API};
+use decompiler::params;
+use decompiler::types::Type;
+use decompiler::untyped_ir::SubroutineKind;
+
+use common::{messages, program};
+
+#[test]
+fn table_covers_the_eight_os_classes() {
+    let mut classes: Vec<&str> = OS_API.iter().map(|s| s.class()).collect();
+    classes.dedup();
+    assert_eq!(classes, ["Math", "String", "Array", "Output", "Screen", "Keyboard", "Memory", "Sys"]);
+
+    let append = os_api::lookup("String.appendChar").unwrap();
+    assert_eq!(append.kind, SubroutineKind::Method);
+    assert_eq!(append.arity(), 2);
+    assert_eq!(append.vm_params(), [Type::String, Type::Char]);
+    assert_eq!(append.ret, Some(Type::String));
+    assert_eq!(os_api::lookup("Screen.drawRectangle").unwrap().ret, None);
+    assert!(os_api::lookup("Main.main").is_none());
+}
+
+#[test]
+fn os_calls_with_the_wrong_argument_count_are_reported() {
+    let program = program("Main", "\
+function Main.main 0
+push constant 1
+push constant 2
+call Math.multiply 2
+push constant 3
+call Output.printInt 2
+push constant 0
+return
+");
+    let mut diags = Vec::new();
+    params::infer(&program, &mut diags);
+    assert_eq!(messages(&diags), ["Main.vm:6: warning: `Output.printInt` takes 1 argument but is called with 2 here"]);
+}
+
+#[test]
+fn os_methods_are_called_on_their_objects() {
+    let (text, diags) = common::jack("Main", "\
+function Main.main 1
+push constant 1
+call String.new 1
+pop local 0
+push local 0
+push constant 65
+call String.appendChar 2
+call String.length 1
+call Output.printInt 1
+pop temp 0
+push local 0
+call String.dispose 1
+pop temp 0
+push constant 0
+return
+", "");
+    assert!(diags.is_empty());
+    assert_eq!(text, "\
+class Main {
+    function void main() {
+        var String local_0;
+        let local_0 = String.new(1);
+        do Output.printInt(String.length(local_0.appendChar(65)));
+        do local_0.dispose();
+        return;
+    }
+}
+");
+}
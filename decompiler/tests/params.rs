@@ -0,0 +1,78 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::params;
+
+use common::{messages, program};
+
+#[test]
+fn count_comes_from_body_and_call_sites() {
+    let program = program("Main", "\
+function Main.f 0
+push argument 1
+return
+function Main.g 0
+push constant 1
+push constant 2
+push constant 3
+call Main.f 3
+return
+function Main.h 0
+push constant 0
+return
+");
+    let mut diags = Vec::new();
+    let counts = params::infer(&program, &mut diags);
+    assert!(diags.is_empty());
+    assert_eq!(counts["Main.f"], 3);
+    assert_eq!(counts["Main.g"], 0);
+    assert_eq!(counts["Main.h"], 0);
+}
+
+#[test]
+fn conflicting_counts_are_reported() {
+    let program = program("Main", "\
+function Main.f 0
+push argument 2
+return
+function Main.g 0
+push constant 1
+call Main.f 1
+push constant 1
+push constant 2
+call Main.f 2
+add
+return
+");
+    let mut diags = Vec::new();
+    let counts = params::infer(&program, &mut diags);
+    assert_eq!(counts["Main.f"], 3);
+    assert_eq!(messages(&diags), [
+        "Main.vm:9: warning: `Main.f` is called with 2 arguments here but with 1 at Main.vm:6",
+        "Main.vm:2: warning: `Main.f` uses argument 2 but is called with 2 arguments",
+    ]);
+}
+
+#[test]
+fn largest_call_site_count_wins() {
+    let program = program("P", "\
+function P.f 0
+push constant 1
+call P.g 1
+push constant 1
+push constant 2
+call P.g 2
+add
+return
+function P.g 0
+push constant 0
+return
+");
+    let mut diags = Vec::new();
+    let counts = params::infer(&program, &mut diags);
+    assert_eq!(counts["P.g"], 2);
+    assert_eq!(messages(&diags), [
+        "P.vm:6: warning: `P.g` is called with 2 arguments here but with 1 at P.vm:3",
+    ]);
+}
@@ -0,0 +1,157 @@
+extern crate decompiler;
+
+use decompiler::parser::{parse, ParseError, ParseErrorKind};
+use decompiler::program::{glob_match, ClassFile, LoadError, Program};
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// The kind, line, column and token of every error in `source`.
+fn errors(source: &str) -> Vec<(ParseErrorKind, usize, usize, String)> {
+    parse("Main.vm", source).err().unwrap().into_iter()
+        .map(|e| (e.kind, e.line, e.column, e.token))
+        .collect()
+}
+
+fn error(source: &str) -> (ParseErrorKind, usize, String) {
+    let mut errors = errors(source);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    let (kind, _, column, token) = errors.remove(0);
+    (kind, column, token)
+}
+
+#[test]
+fn keywords_are_exact() {
+    use ParseErrorKind::*;
+    assert_eq!(error("Push constant 1"), (UnknownCommand, 1, "Push".into()));
+    assert_eq!(error("fun Main.f 0"), (UnknownCommand, 1, "fun".into()));
+    assert_eq!(error("push stack 0"), (UnknownSegment, 6, "stack".into()));
+    assert_eq!(error("push Local 0"), (UnknownSegment, 6, "Local".into()));
+}
+
+#[test]
+fn commands_take_exactly_their_operands() {
+    use ParseErrorKind::*;
+    assert_eq!(error("push"), (MissingOperand, 1, "push".into()));
+    assert_eq!(error("pop local"), (MissingOperand, 5, "local".into()));
+    assert_eq!(error("call Main.f"), (MissingOperand, 6, "Main.f".into()));
+    assert_eq!(error("function"), (MissingOperand, 1, "function".into()));
+    assert_eq!(error("goto"), (MissingOperand, 1, "goto".into()));
+    assert_eq!(error("add 1"), (UnexpectedToken, 5, "1".into()));
+    assert_eq!(error("label A B"), (UnexpectedToken, 9, "B".into()));
+    assert!(parse("Main.vm", "return // done").is_ok());
+}
+
+#[test]
+fn numbers_and_indices_must_be_in_range() {
+    use ParseErrorKind::*;
+    assert_eq!(error("push local x"), (InvalidNumber, 12, "x".into()));
+    assert_eq!(error("call Main.f -1"), (InvalidNumber, 13, "-1".into()));
+    assert_eq!(error("function Main.f two"), (InvalidNumber, 17, "two".into()));
+    assert_eq!(error("push constant -1"), (IndexOutOfRange, 15, "-1".into()));
+    assert_eq!(error("pop temp 8"), (IndexOutOfRange, 10, "8".into()));
+    assert_eq!(error("push pointer 2"), (IndexOutOfRange, 14, "2".into()));
+    assert_eq!(error("push constant 32768"), (IndexOutOfRange, 15, "32768".into()));
+    assert_eq!(error("push constant 40000"), (IndexOutOfRange, 15, "40000".into()));
+    assert!(parse("Main.vm", "pop temp 7\npush pointer 1\npush constant 32767").is_ok());
+}
+
+#[test]
+fn constants_cannot_be_popped_into() {
+    use ParseErrorKind::*;
+    assert_eq!(error("pop constant 3"), (ReadOnlySegment, 5, "constant".into()));
+    assert_eq!(error("pop constant"), (ReadOnlySegment, 5, "constant".into()));
+    let message = parse("Main.vm", "pop constant 3").err().unwrap()[0].to_string();
+    assert_eq!(message, "Main.vm:1:5: cannot pop into segment `constant`");
+}
+
+#[test]
+fn every_error_is_reported_with_its_line_and_column() {
+    let source = "\
+function Main.f 0
+\tpush constant 1 // one
+  pop temp 9
+push argument 0
+   jump END
+return extra
+";
+    assert_eq!(errors(source), [
+        (ParseErrorKind::IndexOutOfRange, 3, 12, "9".to_string()),
+        (ParseErrorKind::UnknownCommand, 5, 4, "jump".to_string()),
+        (ParseErrorKind::UnexpectedToken, 6, 8, "extra".to_string()),
+    ]);
+    let message = parse("Main.vm", source).err().unwrap()[1].to_string();
+    assert_eq!(message, "Main.vm:5:4: unknown command `jump`");
+}
+
+#[test]
+fn commands_before_the_first_function_are_errors() {
+    let errors = ClassFile::parse(Path::new("Main.vm"), "\
+// header
+  push constant 1
+pop temp 0
+function Main.f 0
+push constant 0
+return
+").err().unwrap();
+    assert_eq!(errors, [
+        ParseError { kind: ParseErrorKind::OutsideFunction, file: "Main.vm".into(), line: 2, column: 3, token: "push".into() },
+        ParseError { kind: ParseErrorKind::OutsideFunction, file: "Main.vm".into(), line: 3, column: 1, token: "pop".into() },
+    ]);
+    assert_eq!(errors[0].to_string(), "Main.vm:2:3: command outside a function `push`");
+}
+
+#[test]
+fn classes_are_named_by_their_functions() {
+    let class = ClassFile::parse(Path::new("test.vm"), "function Screen.init 0\npush constant 0\nreturn\n").unwrap();
+    assert_eq!(class.name, "Screen");
+    let class = ClassFile::parse(Path::new("Empty.vm"), "// nothing yet\n").unwrap();
+    assert_eq!(class.name, "Empty");
+
+    let errors = ClassFile::parse(Path::new("B.vm"), "\
+function B.f 0
+push constant 0
+return
+  function A.g 0
+push constant 0
+return
+").err().unwrap();
+    assert_eq!(errors, [
+        ParseError { kind: ParseErrorKind::ForeignFunction, file: "B.vm".into(), line: 4, column: 12, token: "A.g".into() },
+    ]);
+    assert_eq!(errors[0].to_string(), "B.vm:4:12: function of another class `A.g`");
+}
+
+#[test]
+fn functions_defined_twice_are_errors() {
+    let dir = env::temp_dir().join(format!("decompiler-duplicates-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("A.vm"), "function A.f 0\npush constant 0\nreturn\n").unwrap();
+    fs::write(dir.join("B.vm"), "function A.f 0\npush constant 1\nreturn\nfunction A.g 0\npush constant 0\nreturn\n").unwrap();
+    fs::write(dir.join("C.vm"), "function C.f 0\npush constant 0\nreturn\nfunction C.f 0\npush constant 1\nreturn\n").unwrap();
+    let result = Program::load(&[&dir]);
+    fs::remove_dir_all(&dir).unwrap();
+    let errors = match result {
+        Err(LoadError::Parse(errors)) => errors,
+        _ => panic!("expected the duplicates to be reported"),
+    };
+    let found: Vec<(ParseErrorKind, String, usize, String)> = errors.into_iter()
+        .map(|e| (e.kind, Path::new(&e.file).file_name().unwrap().to_string_lossy().into_owned(), e.line, e.token))
+        .collect();
+    assert_eq!(found, [
+        (ParseErrorKind::DuplicateFunction, "B.vm".to_string(), 1, "A.f".to_string()),
+        (ParseErrorKind::DuplicateFunction, "C.vm".to_string(), 4, "C.f".to_string()),
+    ]);
+}
+
+#[test]
+fn function_patterns_match_whole_characters() {
+    assert!(glob_match("Main.*", "Main.main"));
+    assert!(glob_match("*.new", "Point.new"));
+    assert!(!glob_match("Main.*", "Mainly.main"));
+    assert!(!glob_match("*t", "Main.été"));
+    assert!(glob_match("Main.*té", "Main.été"));
+    assert!(glob_match("*.é*", "Main.été"));
+    assert!(glob_match("*é", "Main.été"));
+}
@@ -0,0 +1,165 @@
+extern crate decompiler;
+
+use decompiler::parser::{parse, parse_file, Segment, VmCommand};
+use decompiler::writer::{write_commands, write_file};
+
+/// xorshift64, so the property tests are reproducible without extra crates.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+const SEGMENTS: [Segment; 8] = [
+    Segment::LCL, Segment::ARG, Segment::THIS, Segment::THAT,
+    Segment::CONST, Segment::POINTER, Segment::STATIC, Segment::TEMP,
+];
+
+fn name(rng: &mut Rng) -> String {
+    let first = "abcXYZ_.:$";
+    let rest = "abcXYZ_.:$019";
+    let mut s = String::new();
+    s.push(first.as_bytes()[rng.below(first.len())] as char);
+    for _ in 0..rng.below(12) {
+        s.push(rest.as_bytes()[rng.below(rest.len())] as char);
+    }
+    s
+}
+
+fn command(rng: &mut Rng) -> VmCommand {
+    let segment = SEGMENTS[rng.below(SEGMENTS.len())];
+    let index = match segment {
+        Segment::TEMP => rng.below(8),
+        Segment::POINTER => rng.below(2),
+        _ => rng.below(32768),
+    } as i32;
+    let count = rng.below(10) as i32;
+    match rng.below(17) {
+        0 => VmCommand::Push(segment, index),
+        1 if segment != Segment::CONST => VmCommand::Pop(segment, index),
+        2 => VmCommand::Add,
+        3 => VmCommand::Sub,
+        4 => VmCommand::Neg,
+        5 => VmCommand::Eq,
+        6 => VmCommand::Gt,
+        7 => VmCommand::Lt,
+        8 => VmCommand::And,
+        9 => VmCommand::Or,
+        10 => VmCommand::Not,
+        11 => VmCommand::Label(name(rng)),
+        12 => VmCommand::Goto(name(rng)),
+        13 => VmCommand::IfGoto(name(rng)),
+        14 => VmCommand::Call(name(rng), count),
+        15 => VmCommand::FunDef(name(rng), count),
+        _ => VmCommand::Return,
+    }
+}
+
+/// A random VM file in canonical form, with comments, blank lines,
+/// indentation, trailing whitespace and mixed line endings sprinkled in.
+fn source(rng: &mut Rng) -> String {
+    let mut s = String::new();
+    let lines = rng.below(40);
+    for i in 0..lines {
+        s.push_str(rng.pick(&["", "", " ", "    ", "\t"]));
+        let has_command = rng.below(3) != 0;
+        if has_command {
+            s.push_str(&command(rng).to_string());
+        }
+        if rng.below(3) == 0 {
+            if has_command {
+                s.push_str(rng.pick(&[" ", "  ", "\t"]));
+            }
+            s.push_str("//");
+            s.push_str(rng.pick(&["", " comment", "// nested", " push local 0", "\tx"]));
+        } else if has_command {
+            s.push_str(rng.pick(&["", "", "", " ", "\t", " \t "]));
+        }
+        if i + 1 < lines || rng.below(2) == 0 {
+            s.push_str(rng.pick(&["\n", "\n", "\r\n", "\r\r\n"]));
+        }
+    }
+    s
+}
+
+#[test]
+fn written_commands_parse_back_to_themselves() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..500 {
+        let cmds: Vec<VmCommand> = (0..rng.below(50)).map(|_| command(&mut rng)).collect();
+        let mut out = Vec::new();
+        write_commands(&mut out, &cmds).unwrap();
+        let parsed: Vec<VmCommand> = parse("gen.vm", &String::from_utf8(out).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|c| c.node)
+            .collect();
+        assert_eq!(parsed, cmds);
+    }
+}
+
+#[test]
+fn canonical_files_round_trip_byte_for_byte() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..500 {
+        let text = source(&mut rng);
+        let file = parse_file("gen.vm", &text).unwrap();
+        let mut out = Vec::new();
+        write_file(&mut out, &file, true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+    }
+}
+
+#[test]
+fn writing_normalizes_to_a_fixed_point() {
+    let text = "  push   local 3 // keep\r\n\tADD\nlabel\tLOOP\n";
+    assert!(parse_file("odd.vm", text).is_err());
+    let text = "  push   local 3 // keep\r\n\tadd\nlabel\tLOOP";
+    let file = parse_file("odd.vm", text).unwrap();
+    let mut once = Vec::new();
+    write_file(&mut once, &file, true).unwrap();
+    let once = String::from_utf8(once).unwrap();
+    assert_eq!(once, "  push local 3 // keep\r\n\tadd\nlabel LOOP");
+    let mut twice = Vec::new();
+    write_file(&mut twice, &parse_file("odd.vm", &once).unwrap(), true).unwrap();
+    assert_eq!(String::from_utf8(twice).unwrap(), once);
+}
+
+#[test]
+fn whitespace_and_line_endings_are_kept_per_line() {
+    for text in &["push local 0 \n", "add\t\n", "add\r\npush local 0\n\r\n", "neg\r\r\nnot"] {
+        let file = parse_file("ws.vm", text).unwrap();
+        let mut out = Vec::new();
+        write_file(&mut out, &file, true).unwrap();
+        assert_eq!(&String::from_utf8(out).unwrap(), text);
+    }
+}
+
+#[test]
+fn compiled_vm_round_trips() {
+    let text = include_str!("../test.vm");
+    let file = parse_file("test.vm", text).unwrap();
+    let mut out = Vec::new();
+    write_file(&mut out, &file, true).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), text);
+
+    let mut bare = Vec::new();
+    write_file(&mut bare, &file, false).unwrap();
+    let commands: Vec<VmCommand> = file.commands().into_iter().map(|c| c.node).collect();
+    let mut expected = Vec::new();
+    write_commands(&mut expected, &commands).unwrap();
+    assert_eq!(bare, expected);
+}
@@ -0,0 +1,134 @@
+extern crate decompiler;
+
+mod common;
+
+use common::untyped;
+use decompiler::decompiler::Options;
+use decompiler::parser::parse;
+use decompiler::untyped_ir::UnTypedIR;
+
+/// The body of the first subroutine `source` decompiles to.
+fn body(source: &str) -> Vec<UnTypedIR> {
+    let (mut classes, diags) = untyped("Main", source, Options::default());
+    assert!(diags.is_empty(), "{:?}", diags);
+    match classes.remove(0).subroutines.remove(0) {
+        UnTypedIR::FuncDef(_, body) => body,
+        s => panic!("not a subroutine: {}", s),
+    }
+}
+
+/// The file and first and last line of the statement `ir`.
+fn lines(ir: &UnTypedIR) -> (String, usize, usize) {
+    let span = ir.span().unwrap_or_else(|| panic!("no span on {}", ir));
+    (span.file.to_string(), span.first_line, span.last_line)
+}
+
+fn at(first: usize, last: usize) -> (String, usize, usize) {
+    ("Main.vm".to_string(), first, last)
+}
+
+#[test]
+fn every_command_spans_its_own_line() {
+    let cmds = parse("Main.vm", "function Main.f 0\n\n  push constant 1 // one\nreturn\n").unwrap();
+    let spans: Vec<(String, usize, usize)> = cmds.iter()
+        .map(|c| (c.span.file.to_string(), c.span.first_line, c.span.last_line))
+        .collect();
+    assert_eq!(spans, [at(1, 1), at(3, 3), at(4, 4)]);
+}
+
+#[test]
+fn statement_spans_the_commands_it_was_built_from() {
+    let body = body("\
+function Main.f 1
+push argument 0
+push argument 1
+add
+pop local 0
+push local 0
+return
+");
+    assert_eq!(body.iter().map(lines).collect::<Vec<_>>(), [at(2, 5), at(6, 7)]);
+}
+
+#[test]
+fn loop_spans_its_test_up_to_the_jump() {
+    let body = body("\
+function Main.f 1
+label LOOP
+push local 0
+push constant 10
+lt
+not
+if-goto END
+push local 0
+push constant 1
+add
+pop local 0
+goto LOOP
+label END
+push constant 0
+return
+");
+    assert_eq!(body.len(), 1);
+    assert_eq!(lines(&body[0]), at(3, 7));
+    match body[0].unspanned() {
+        UnTypedIR::While(_, inner, rest) => {
+            assert_eq!(inner.iter().map(lines).collect::<Vec<_>>(), [at(8, 11)]);
+            assert_eq!(rest.iter().map(lines).collect::<Vec<_>>(), [at(14, 15)]);
+        }
+        s => panic!("not a loop: {}", s),
+    }
+}
+
+#[test]
+fn array_write_spans_both_statements_of_the_temp_swap() {
+    // `let a[i] = b[j];` reads `b[j]` into `temp 0` first. The two
+    // statements become one, spanning all of their commands.
+    let body = body("\
+function Main.f 2
+push local 0
+push argument 0
+add
+push local 1
+push argument 1
+add
+pop pointer 1
+push that 0
+pop temp 0
+pop pointer 1
+push temp 0
+pop that 0
+push constant 0
+return
+");
+    assert_eq!(body.len(), 2, "{:?}", body);
+    assert_eq!(lines(&body[0]), at(2, 13));
+}
+
+#[test]
+fn values_carried_into_a_block_keep_their_own_statements() {
+    let body = body("\
+function Main.f 0
+push constant 5
+push argument 0
+if-goto T
+push constant 1
+goto J
+label T
+push constant 2
+label J
+add
+return
+");
+    let spans: Vec<(String, usize, usize)> = body.iter().map(lines).collect();
+    assert_eq!(spans, [at(2, 2), at(3, 4)]);
+    match body[1].unspanned() {
+        UnTypedIR::If(_, ts, fs, rest) => {
+            assert_eq!(ts.iter().map(lines).collect::<Vec<_>>(), [at(8, 8)]);
+            assert_eq!(fs.iter().map(lines).collect::<Vec<_>>(), [at(5, 5)]);
+            // The sum is built where both values meet.
+            assert_eq!(rest.iter().map(lines).collect::<Vec<_>>(), [at(10, 11)]);
+        }
+        s => panic!("not a branch: {}", s),
+    }
+}
@@ -0,0 +1,86 @@
+extern crate decompiler;
+
+mod common;
+
+use common::program;
+use decompiler::decompiler::function_graph;
+use decompiler::diagnostic::Severity;
+use decompiler::stack;
+
+/// The severity, first line and message of every problem `stack::verify`
+/// finds in the only function of `source`.
+fn verify(source: &str) -> Vec<(Severity, usize, String)> {
+    let program = program("Main", source);
+    let graph = function_graph(&program.classes[0].functions[0]).unwrap();
+    let mut diags = Vec::new();
+    stack::verify(&graph, &mut diags);
+    diags.into_iter()
+        .map(|d| (d.severity, d.span.unwrap().first_line, d.message))
+        .collect()
+}
+
+#[test]
+fn balanced_function_has_no_problems() {
+    assert!(verify("\
+function Main.f 0
+push argument 0
+if-goto T
+push constant 1
+return
+label T
+push constant 2
+return
+").is_empty());
+}
+
+#[test]
+fn underflow_is_reported_at_the_command() {
+    let diags = verify("\
+function Main.f 0
+push constant 1
+add
+return
+");
+    assert_eq!(diags.len(), 1);
+    let (severity, line, ref message) = diags[0];
+    assert_eq!((severity, line), (Severity::Error, 3));
+    assert!(message.starts_with("stack underflow: `add` needs 2 values"), "{}", message);
+}
+
+#[test]
+fn paths_meeting_with_different_depths_are_reported_where_they_meet() {
+    let diags = verify("\
+function Main.f 0
+push argument 0
+if-goto T
+push constant 1
+push constant 2
+goto E
+label T
+push constant 3
+label E
+pop local 0
+push constant 0
+return
+");
+    // `E` keeps the depth the `goto` reached it with, so the `return`
+    // is left with a value too.
+    assert_eq!(diags, [
+        (Severity::Error, 12, "`return` leaves 1 value on the stack".to_string()),
+        (Severity::Error, 8, "stack depth mismatch at `E`: 1 value on this path but 2 on another".to_string()),
+    ]);
+}
+
+#[test]
+fn values_left_at_return_are_reported() {
+    let diags = verify("\
+function Main.f 0
+push constant 1
+push constant 2
+return
+");
+    assert_eq!(diags.len(), 1);
+    let (severity, line, ref message) = diags[0];
+    assert_eq!((severity, line), (Severity::Error, 4));
+    assert_eq!(message, "`return` leaves 1 value on the stack");
+}
@@ -0,0 +1,57 @@
+extern crate decompiler;
+
+mod common;
+
+use common::ir;
+
+#[test]
+fn value_pushed_before_a_branch_is_used_after_the_join() {
+    let text = ir("Main", "\
+function Main.f 0
+push constant 5
+push argument 0
+if-goto ELSE
+push constant 1
+pop argument 1
+label ELSE
+push constant 2
+add
+return
+");
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Main {",
+        "function Main.f(ARG_0, ARG_1) {",
+        "let STACK_0 = 5;",
+        "if (~(ARG_0)) {",
+        "let ARG_1 = 1;",
+        "}",
+        "return(STACK_0 + 2);",
+        "}",
+        "}",
+    ]);
+}
+
+#[test]
+fn stacked_read_keeps_the_value_from_before_the_assignment() {
+    let text = ir("Main", "\
+function Main.g 1
+push local 0
+push constant 1
+pop local 0
+push local 0
+add
+return
+");
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    assert_eq!(lines, [
+        "class Main {",
+        "function Main.g() {",
+        "var LCL_0;",
+        "let STACK_0 = LCL_0;",
+        "let LCL_0 = 1;",
+        "return(STACK_0 + LCL_0);",
+        "}",
+        "}",
+    ]);
+}
@@ -0,0 +1,145 @@
+extern crate decompiler;
+
+mod common;
+
+use decompiler::decompiler::Options;
+use decompiler::types::{DeclaredClass, Type};
+
+fn infer(name: &str, source: &str) -> (Vec<DeclaredClass>, Vec<String>) {
+    common::typed(name, source, "", Options::default())
+}
+
+#[test]
+fn evidence_from_os_calls_arrays_strings_and_call_sites() {
+    let (classes, diags) = infer("Main", "\
+function Main.main 3
+push constant 10
+call Array.new 1
+pop local 0
+push constant 1
+call String.new 1
+push constant 72
+call String.appendChar 2
+pop local 1
+push local 1
+push constant 0
+call String.charAt 2
+pop local 2
+push local 0
+push local 1
+call Main.show 2
+pop temp 0
+push constant 0
+return
+function Main.show 0
+push argument 1
+call Output.printString 1
+pop temp 0
+push constant 0
+return
+");
+    assert!(diags.is_empty(), "{:?}", diags);
+    let main = &classes[0].subroutines[0];
+    assert_eq!(main.var_type("LCL_0"), Some(&Type::Array));
+    assert_eq!(main.var_type("LCL_1"), Some(&Type::String));
+    assert_eq!(main.var_type("LCL_2"), Some(&Type::Char));
+    assert_eq!(main.ret, None);
+    let show = &classes[0].subroutines[1];
+    assert_eq!(show.var_type("ARG_0"), Some(&Type::Array));
+    assert_eq!(show.var_type("ARG_1"), Some(&Type::String));
+}
+
+#[test]
+fn unresolved_variables_fall_back_to_int_with_a_warning() {
+    let (classes, diags) = infer("Main", "\
+function Main.f 1
+push argument 0
+pop local 0
+push local 0
+return
+");
+    let f = &classes[0].subroutines[0];
+    assert_eq!(f.var_type("LCL_0"), Some(&Type::Int));
+    assert_eq!(f.ret, Some(Type::Int));
+    assert_eq!(diags, [
+        "Main.vm:2-3: warning: cannot infer the type of `ARG_0` in Main.f; declared int",
+        "Main.vm:2-3: warning: cannot infer the type of `LCL_0` in Main.f; declared int",
+        "Main.vm:4-5: warning: cannot infer the type of the result of Main.f; declared int",
+    ]);
+}
+
+#[test]
+fn made_up_variables_are_typed_without_a_warning() {
+    // A loop entered at both `A` and `B` becomes a state machine, whose
+    // STATE only ever meets constants.
+    let (classes, diags) = infer("Main", "\
+function Main.f 1
+push argument 0
+if-goto B
+label A
+push local 0
+push constant 1
+add
+pop local 0
+label B
+push local 0
+push constant 10
+lt
+if-goto A
+push local 0
+return
+");
+    assert_eq!(classes[0].subroutines[0].var_type("STATE"), Some(&Type::Int));
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert!(diags[0].contains("decompiled as a state machine"), "{:?}", diags);
+}
+
+#[test]
+fn warnings_point_into_the_subroutine_they_are_about() {
+    let (_, diags) = infer("Main", "\
+function Main.f 0
+push static 0
+call Main.g 1
+pop static 1
+push constant 0
+return
+function Main.g 0
+push argument 0
+return
+");
+    assert!(diags.contains(&"Main.vm:8-9: warning: cannot infer the type of `ARG_0` in Main.g; declared int".to_string()), "{:?}", diags);
+}
+
+#[test]
+fn and_or_are_only_logical_with_a_boolean_operand() {
+    let source = "\
+function Main.f 1
+push constant 0
+pop local 0
+push local 0
+push constant 1
+and
+not
+if-goto END
+push constant 2
+pop local 0
+label END
+push local 0
+return
+function Main.g 1
+push argument 0
+push constant 1
+lt
+push local 0
+and
+return
+";
+    let (classes, _) = infer("Main", source);
+    let (f, g) = (&classes[0].subroutines[0], &classes[0].subroutines[1]);
+    assert_eq!(f.var_type("LCL_0"), Some(&Type::Int));
+    assert_eq!(g.var_type("LCL_0"), Some(&Type::Boolean));
+    assert_eq!(g.ret, Some(Type::Boolean));
+
+    let (text, _) = common::jack("Main", source, "");
+    assert!(text.contains("let local_0 = 0;"), "{}", text);
+}